@@ -0,0 +1,49 @@
+use super::input::{ActionState, Axis};
+use super::levels::Level;
+use super::objects::{Player, SPEED};
+use super::pathfinder::{PathError, Pathfinder};
+use super::simulation::TIME_STEP;
+use graph4d::geometry::Vector;
+
+/// How close the player has to get to a waypoint to go on to the next one.
+const TOLERANCE: f64 = 1e-3;
+
+/// Plays a level by walking along the path found by `Pathfinder`, without turning.
+pub struct Bot {
+    path: Vec<Vector>,
+    next: usize,
+}
+
+impl Bot {
+    pub fn new(level: &mut Level) -> Result<Bot, PathError> {
+        let start = level.player().position();
+        let path = Pathfinder::new(level).find_path(start)?;
+        Ok(Bot {
+            path: path,
+            next: 0,
+        })
+    }
+
+    /// What to do in the next step of the simulation.
+    pub fn actions(&mut self, player: &Player) -> ActionState {
+        let mut actions = ActionState::new();
+        let position = player.position();
+        while self.next < self.path.len() && (self.path[self.next] - position).len() < TOLERANCE {
+            self.next += 1;
+        }
+        if self.next == self.path.len() {
+            return actions;
+        }
+
+        // full speed towards the waypoint, slowing down so as to stop right at it
+        let direction = self.path[self.next] - position;
+        let distance = direction.len();
+        let speed = (distance / (SPEED * TIME_STEP)).min(1.0) / distance;
+        let [right, up, front, ana] = player.axes();
+        actions.set_axis(Axis::MoveRight, direction.dot(right) * speed);
+        actions.set_axis(Axis::MoveUp, direction.dot(up) * speed);
+        actions.set_axis(Axis::MoveForward, direction.dot(front) * speed);
+        actions.set_axis(Axis::MoveAna, direction.dot(ana) * speed);
+        actions
+    }
+}
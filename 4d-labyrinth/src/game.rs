@@ -0,0 +1,392 @@
+use super::input::ActionState;
+use super::levels::{Level, StartPose};
+use super::objects::Player;
+use super::pack::PackEntry;
+use super::pathfinder::Pathfinder;
+use super::replay::{Recorder, ReplayOrigin};
+use super::save::SavedGame;
+use super::simulation::{Simulation, TIME_STEP};
+use std::collections::VecDeque;
+
+/// A level ready to be played, with the details from its pack.
+pub struct Stage {
+    pub title: String,
+    pub par: Option<f64>,
+    /// Vertical field of view in degrees.
+    pub fov: Option<f64>,
+    pub level: Level,
+}
+
+impl Stage {
+    pub fn load(entry: &PackEntry) -> Result<Stage, String> {
+        let mut level = entry
+            .load()
+            .map_err(|err| format!("Error loading {}: {}", entry.file().display(), err))?;
+        let start = level.player().position();
+        if let Err(err) = Pathfinder::new(&level).find_path(start) {
+            eprintln!(
+                "Warning: {} can't be completed: {}",
+                entry.file().display(),
+                err
+            );
+        }
+        if let Some(camera) = entry.settings().camera {
+            level.player().set_camera_offset(camera);
+        }
+        Ok(Stage {
+            title: entry.title(),
+            par: entry.par(),
+            fov: entry.settings().fov,
+            level: level,
+        })
+    }
+}
+
+/// How a level went.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LevelResult {
+    /// The number of the level, starting from 1.
+    pub number: usize,
+    /// Simulated time in seconds.
+    pub time: f64,
+    pub par: Option<f64>,
+}
+
+/// The level being played.
+struct Playing {
+    number: usize,
+    title: String,
+    par: Option<f64>,
+    fov: Option<f64>,
+    simulation: Simulation,
+}
+
+/// A run through a list of levels: it plays them one after another until the last one is
+/// completed.
+///
+/// The game only knows about the player's actions, not about windows or devices, so it can be
+/// played by tests and bots as well as by people.
+pub struct Game {
+    upcoming: VecDeque<Stage>,
+    playing: Option<Playing>,
+    completed: usize,
+    // the recording once the game is finished; before that it's kept by the simulation
+    recorder: Option<Recorder>,
+}
+
+impl Game {
+    pub fn new(stages: Vec<Stage>) -> Game {
+        let mut game = Game {
+            upcoming: stages.into_iter().collect(),
+            playing: None,
+            completed: 0,
+            recorder: None,
+        };
+        game.next_stage();
+        game
+    }
+
+    fn next_stage(&mut self) {
+        let recorder = match self.playing.take() {
+            Some(mut playing) => playing.simulation.take_recorder(),
+            None => self.recorder.take(),
+        };
+        self.playing = self.upcoming.pop_front().map(|stage| Playing {
+            number: self.completed + 1,
+            title: stage.title,
+            par: stage.par,
+            fov: stage.fov,
+            simulation: Simulation::new(stage.level),
+        });
+        match self.playing {
+            Some(ref mut playing) => playing.simulation.set_recorder(recorder),
+            None => self.recorder = recorder,
+        }
+    }
+
+    /// Records the actions of every step from now on, across all the remaining levels. The
+    /// recording is marked with the `origin` it has to be played back on.
+    pub fn start_recording(&mut self, origin: ReplayOrigin) {
+        let recorder = Some(Recorder::new(TIME_STEP, origin));
+        match self.playing {
+            Some(ref mut playing) => playing.simulation.set_recorder(recorder),
+            None => self.recorder = recorder,
+        }
+    }
+
+    pub fn recording(&self) -> Option<&Recorder> {
+        match self.playing {
+            Some(ref playing) => playing.simulation.recorder(),
+            None => self.recorder.as_ref(),
+        }
+    }
+
+    /// The title and the fingerprint of the level being played and of the ones after it.
+    pub fn levels(&mut self) -> Vec<(String, u64)> {
+        let playing = self.playing.as_mut().map(|playing| {
+            (
+                playing.title.clone(),
+                playing.simulation.level_mut().fingerprint(),
+            )
+        });
+        let upcoming = self
+            .upcoming
+            .iter()
+            .map(|stage| (stage.title.clone(), stage.level.fingerprint()));
+        playing.into_iter().chain(upcoming).collect()
+    }
+
+    /// Whether all the levels have been completed.
+    pub fn is_finished(&self) -> bool {
+        self.playing.is_none()
+    }
+
+    /// The number of the level being played, starting from 1.
+    pub fn level_number(&self) -> Option<usize> {
+        self.playing.as_ref().map(|playing| playing.number)
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.playing.as_ref().map(|playing| &playing.title[..])
+    }
+
+    pub fn fov(&self) -> Option<f64> {
+        self.playing.as_ref().and_then(|playing| playing.fov)
+    }
+
+    pub fn level_mut(&mut self) -> Option<&mut Level> {
+        self.playing
+            .as_mut()
+            .map(|playing| playing.simulation.level_mut())
+    }
+
+    /// The progress in the level being played, or `None` if the game is finished.
+    pub fn save(&mut self) -> Option<SavedGame> {
+        let playing = self.playing.as_mut()?;
+        Some(SavedGame {
+            level: playing.number,
+            title: playing.title.clone(),
+            fingerprint: playing.simulation.level_mut().fingerprint(),
+            time: playing.simulation.elapsed(),
+            pose: playing.simulation.level_mut().player().pose(),
+        })
+    }
+
+    /// Skips to the saved level and puts the player where they were. The game has to be at the
+    /// start of the same levels it was saved with, and the saved pose has to be a valid start in
+    /// the level.
+    pub fn resume(&mut self, saved: &SavedGame) -> Result<(), String> {
+        let current = self.level_number().ok_or("there are no levels left")?;
+        let stage = match saved.level {
+            level if level == current => self.playing.as_mut().map(|playing| {
+                let title = &playing.title[..];
+                (title, playing.simulation.level_mut())
+            }),
+            level if level > current => self
+                .upcoming
+                .get_mut(level - current - 1)
+                .map(|stage| (&stage.title[..], &mut stage.level)),
+            _ => None,
+        };
+        let (title, level) = match stage {
+            Some(stage) => stage,
+            None => return Err(format!("there is no level {}", saved.level)),
+        };
+        if title != saved.title || level.fingerprint() != saved.fingerprint {
+            return Err(format!(
+                "level {} isn't the `{}` level that was saved",
+                saved.level, saved.title
+            ));
+        }
+        let pose = StartPose {
+            position: saved.pose.position,
+            right: saved.pose.right,
+            up: saved.pose.up,
+            front: saved.pose.front,
+            ana: saved.pose.ana,
+        };
+        level
+            .check_pose(&pose)
+            .map_err(|err| format!("invalid saved pose: {}", err))?;
+
+        for _ in current..saved.level {
+            self.completed += 1;
+            self.next_stage();
+        }
+        let playing = self.playing.as_mut().unwrap();
+        playing.simulation.set_elapsed(saved.time);
+        playing
+            .simulation
+            .level_mut()
+            .player()
+            .set_pose(&saved.pose);
+        Ok(())
+    }
+
+    /// Simulates the time of a frame. When it completes the level, the game moves on to the
+    /// next one and the result of the completed one is returned.
+    pub fn update(&mut self, frame_time: f64, actions: &ActionState) -> Option<LevelResult> {
+        match self.playing {
+            Some(ref mut playing) => playing.simulation.update(frame_time, actions),
+            None => return None,
+        };
+        self.check_completed()
+    }
+
+    /// Simulates the time of a frame like `update`, asking `controller` what the player does
+    /// before every step of the simulation.
+    pub fn update_with<F>(&mut self, frame_time: f64, controller: F) -> Option<LevelResult>
+    where
+        F: FnMut(&Player) -> ActionState,
+    {
+        match self.playing {
+            Some(ref mut playing) => playing.simulation.update_with(frame_time, controller),
+            None => return None,
+        };
+        self.check_completed()
+    }
+
+    fn check_completed(&mut self) -> Option<LevelResult> {
+        let result = match self.playing {
+            Some(ref playing) if playing.simulation.completed() => LevelResult {
+                number: playing.number,
+                time: playing.simulation.elapsed(),
+                par: playing.par,
+            },
+            _ => return None,
+        };
+        self.completed += 1;
+        self.next_stage();
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::bot::Bot;
+    use super::super::generator::Maze;
+    use super::super::input::{Action, ActionState};
+    use super::super::levels::Level;
+    use super::super::pack::Pack;
+    use super::super::save::SavedGame;
+    use super::super::simulation::TIME_STEP;
+    use super::{Game, Stage};
+    use graph4d::geometry::Vector;
+
+    /// The steps the bot gets to complete a level, a few minutes of game time.
+    const MAX_STEPS: usize = 120 * 300;
+
+    fn builtin_game() -> Game {
+        let pack = Pack::builtin();
+        let stages = pack.entries().iter().map(Stage::load);
+        Game::new(stages.collect::<Result<_, _>>().unwrap())
+    }
+
+    #[test]
+    fn test_idle_player() {
+        let mut game = builtin_game();
+        assert_eq!(game.level_number(), Some(1));
+        assert_eq!(game.title(), Some("First steps"));
+        for _ in 0..100 {
+            assert!(game.update(0.1, &ActionState::new()).is_none());
+        }
+        assert_eq!(game.level_number(), Some(1));
+    }
+
+    #[test]
+    fn test_bot_completes_builtin_levels() {
+        let mut game = builtin_game();
+        let mut results = Vec::new();
+        while !game.is_finished() {
+            let number = game.level_number().unwrap();
+            let mut bot = Bot::new(game.level_mut().unwrap()).unwrap();
+            for _ in 0..MAX_STEPS {
+                if let Some(result) = game.update_with(TIME_STEP, |player| bot.actions(player)) {
+                    results.push(result);
+                    break;
+                }
+            }
+            assert_eq!(
+                results.len(),
+                number,
+                "the bot got stuck in level {}",
+                number
+            );
+        }
+
+        assert_eq!(results.len(), 2);
+        for (i, result) in results.iter().enumerate() {
+            assert_eq!(result.number, i + 1);
+            assert!(result.time > 0.0);
+            assert!(result.time < result.par.unwrap());
+        }
+        assert!(game.update(TIME_STEP, &ActionState::new()).is_none());
+    }
+
+    #[test]
+    fn test_save_and_resume() {
+        // finish the first level and play a bit of the second one
+        let mut game = builtin_game();
+        let mut bot = Bot::new(game.level_mut().unwrap()).unwrap();
+        while game.level_number() == Some(1) {
+            game.update_with(TIME_STEP, |player| bot.actions(player));
+        }
+        let mut bot = Bot::new(game.level_mut().unwrap()).unwrap();
+        for _ in 0..200 {
+            game.update_with(TIME_STEP, |player| bot.actions(player));
+        }
+        let mut file = Vec::new();
+        game.save().unwrap().write_to(&mut file).unwrap();
+
+        let mut resumed = builtin_game();
+        let saved = SavedGame::from_reader(&file[..]).unwrap();
+        resumed.resume(&saved).unwrap();
+        assert_eq!(resumed.level_number(), Some(2));
+
+        // both games go on in the same way
+        let mut actions = ActionState::new();
+        actions.set(Action::MoveForward, true);
+        actions.set(Action::RotateYWPlus, true);
+        for _ in 0..100 {
+            game.update(TIME_STEP, &actions);
+            resumed.update(TIME_STEP, &actions);
+        }
+        let (expected, saved) = (game.save().unwrap(), resumed.save().unwrap());
+        assert_eq!(saved.time, expected.time);
+        assert!(saved.pose.position == expected.pose.position);
+        assert!(saved.pose.front == expected.pose.front);
+        assert_eq!(
+            saved.pose.orientation.as_array(),
+            expected.pose.orientation.as_array()
+        );
+
+        let mut other_levels = Game::new(vec![Stage::load(&Pack::builtin().entries()[1]).unwrap()]);
+        assert!(other_levels.resume(&saved).is_err());
+    }
+
+    fn random_maze(seed: u64) -> Game {
+        Game::new(vec![Stage {
+            title: "Random maze 3x3x3x3".to_owned(),
+            par: None,
+            fov: None,
+            level: Level::from_cells(Maze::generate(3, seed).cells()).unwrap(),
+        }])
+    }
+
+    #[test]
+    fn test_resume_checks() {
+        let mut game = random_maze(1);
+        let saved = game.save().unwrap();
+        assert!(random_maze(1).resume(&saved).is_ok());
+        // the same title, but a different maze
+        assert!(random_maze(2).resume(&saved).is_err());
+
+        let mut outside = game.save().unwrap();
+        outside.pose.position = Vector::new(-100.0, 0.0, 0.0, 0.0);
+        assert!(random_maze(1).resume(&outside).is_err());
+
+        let mut mirrored = game.save().unwrap();
+        mirrored.pose.ana = mirrored.pose.ana * -1.0;
+        assert!(random_maze(1).resume(&mirrored).is_err());
+    }
+}
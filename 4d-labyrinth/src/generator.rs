@@ -0,0 +1,291 @@
+use super::levels::{Cell, CellContents};
+use graph4d::geometry::Vector;
+use std::collections::VecDeque;
+
+/// The edge length of a single maze cell - the same as in the hand-made levels.
+const CELL_SIZE: f64 = 5.0;
+
+const WALL_LETTERS: [(char, char); 4] = [('x', 'X'), ('y', 'Y'), ('z', 'Z'), ('w', 'W')];
+
+/// A xorshift64* generator - good enough for mazes, and it produces the same sequence from
+/// the same seed on every platform.
+struct Random {
+    state: u64,
+}
+
+impl Random {
+    fn new(seed: u64) -> Random {
+        // splitmix64 scrambling, so that similar seeds give unrelated sequences and the state
+        // is never zero
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        Random {
+            state: if z == 0 { 1 } else { z },
+        }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// A perfect maze (exactly one route between any two cells) on an N×N×N×N grid of
+/// hypercubic cells. The player starts in the cell at the origin, and the target is placed
+/// in the cell furthest away from it.
+pub struct Maze {
+    size: usize,
+    // open[i][axis] - whether cell i is connected to its neighbour in the positive direction
+    open: Vec<[bool; 4]>,
+    target: [usize; 4],
+}
+
+impl Maze {
+    pub fn generate(size: usize, seed: u64) -> Maze {
+        assert!(size >= 2, "a maze needs at least 2 cells along every axis");
+        let mut rng = Random::new(seed);
+        let mut maze = Maze {
+            size: size,
+            open: vec![[false; 4]; size * size * size * size],
+            target: [0; 4],
+        };
+
+        // randomized depth-first search starting at the player's cell
+        let mut visited = vec![false; maze.open.len()];
+        let mut stack = vec![[0; 4]];
+        visited[0] = true;
+        while let Some(&current) = stack.last() {
+            let unvisited: Vec<_> = maze
+                .neighbours(current)
+                .into_iter()
+                .filter(|&(_, cell)| !visited[maze.index(cell)])
+                .collect();
+            if unvisited.is_empty() {
+                stack.pop();
+                continue;
+            }
+            let (axis, next) = unvisited[rng.below(unvisited.len())];
+            let lower = if next[axis] > current[axis] {
+                current
+            } else {
+                next
+            };
+            let lower = maze.index(lower);
+            maze.open[lower][axis] = true;
+            visited[maze.index(next)] = true;
+            stack.push(next);
+        }
+
+        let distances = maze.distances_from([0; 4]);
+        let furthest = (0..distances.len())
+            .max_by_key(|&i| (distances[i], i))
+            .unwrap();
+        maze.target = maze.coords(furthest);
+        maze
+    }
+
+    fn index(&self, cell: [usize; 4]) -> usize {
+        ((cell[3] * self.size + cell[2]) * self.size + cell[1]) * self.size + cell[0]
+    }
+
+    fn coords(&self, mut index: usize) -> [usize; 4] {
+        let mut cell = [0; 4];
+        for coord in cell.iter_mut() {
+            *coord = index % self.size;
+            index /= self.size;
+        }
+        cell
+    }
+
+    /// All cells sharing a face with `cell`, together with the axis they are adjacent along.
+    fn neighbours(&self, cell: [usize; 4]) -> Vec<(usize, [usize; 4])> {
+        let mut result = Vec::new();
+        for axis in 0..4 {
+            if cell[axis] > 0 {
+                let mut other = cell;
+                other[axis] -= 1;
+                result.push((axis, other));
+            }
+            if cell[axis] + 1 < self.size {
+                let mut other = cell;
+                other[axis] += 1;
+                result.push((axis, other));
+            }
+        }
+        result
+    }
+
+    /// Whether there is a passage between two adjacent cells.
+    pub fn is_open(&self, cell: [usize; 4], axis: usize, positive: bool) -> bool {
+        if positive {
+            cell[axis] + 1 < self.size && self.open[self.index(cell)][axis]
+        } else {
+            cell[axis] > 0 && {
+                let mut other = cell;
+                other[axis] -= 1;
+                self.open[self.index(other)][axis]
+            }
+        }
+    }
+
+    fn distances_from(&self, start: [usize; 4]) -> Vec<usize> {
+        let mut distances = vec![usize::max_value(); self.open.len()];
+        let mut queue = VecDeque::new();
+        distances[self.index(start)] = 0;
+        queue.push_back(start);
+        while let Some(cell) = queue.pop_front() {
+            let distance = distances[self.index(cell)];
+            for (axis, next) in self.neighbours(cell) {
+                let open = self.is_open(cell, axis, next[axis] > cell[axis]);
+                if open && distances[self.index(next)] == usize::max_value() {
+                    distances[self.index(next)] = distance + 1;
+                    queue.push_back(next);
+                }
+            }
+        }
+        distances
+    }
+
+    /// The maze as level cells. Every cell lists the walls on its lower faces, and the cells on
+    /// the far boundary of the grid also list their upper faces, so that no wall is repeated.
+    /// The target gets an additional `T` block covering its cell.
+    pub fn cells(&self) -> Vec<Cell> {
+        let mut cells = Vec::new();
+        for i in 0..self.open.len() {
+            let cell = self.coords(i);
+            let mut letters = String::new();
+            for (axis, &(lower, upper)) in WALL_LETTERS.iter().enumerate() {
+                if !self.is_open(cell, axis, false) {
+                    letters.push(lower);
+                }
+                if cell[axis] + 1 == self.size {
+                    letters.push(upper);
+                }
+            }
+            let (corner1, corner2) = Maze::corners(cell);
+            cells.push(Cell::new(corner1, corner2, CellContents::Walls(letters)));
+        }
+
+        let (corner1, corner2) = Maze::corners(self.target);
+        cells.push(Cell::new(corner1, corner2, CellContents::Target));
+        cells
+    }
+
+    fn corners(cell: [usize; 4]) -> (Vector, Vector) {
+        let center = |i: usize| cell[i] as f64 * CELL_SIZE;
+        let half = CELL_SIZE / 2.0;
+        (
+            Vector::new(
+                center(0) - half,
+                center(1) - half,
+                center(2) - half,
+                center(3) - half,
+            ),
+            Vector::new(
+                center(0) + half,
+                center(1) + half,
+                center(2) + half,
+                center(3) + half,
+            ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::levels::{CellContents, Level};
+    use super::{Maze, CELL_SIZE, WALL_LETTERS};
+    use std::collections::{HashMap, VecDeque};
+
+    fn grid_coords(v: graph4d::geometry::Vector) -> [usize; 4] {
+        let coord = |c: f64| ((c + CELL_SIZE / 2.0) / CELL_SIZE).round() as usize;
+        [coord(v.x()), coord(v.y()), coord(v.z()), coord(v.w())]
+    }
+
+    #[test]
+    fn test_every_cell_reaches_target() {
+        for seed in 0..5 {
+            let maze = Maze::generate(4, seed);
+            let size = maze.size;
+
+            // collect the wall letters of every grid cell from the emitted level cells
+            let mut letters: HashMap<[usize; 4], String> = HashMap::new();
+            let mut target = None;
+            for cell in maze.cells() {
+                let coords = grid_coords(cell.min());
+                match *cell.contents() {
+                    CellContents::Walls(ref l) => letters.entry(coords).or_default().push_str(l),
+                    CellContents::Target => target = Some(coords),
+                }
+            }
+            let target = target.unwrap();
+            assert_eq!(target, maze.target);
+
+            // flood fill from the target through faces without walls
+            let mut reached = vec![target];
+            let mut queue = VecDeque::new();
+            queue.push_back(target);
+            while let Some(cell) = queue.pop_front() {
+                for (axis, &(lower, upper)) in WALL_LETTERS.iter().enumerate() {
+                    let mut neighbours = Vec::new();
+                    if cell[axis] > 0 {
+                        let mut other = cell;
+                        other[axis] -= 1;
+                        neighbours.push((other, lower, upper));
+                    }
+                    if cell[axis] + 1 < size {
+                        let mut other = cell;
+                        other[axis] += 1;
+                        neighbours.push((other, upper, lower));
+                    }
+                    for (other, ours, theirs) in neighbours {
+                        let blocked =
+                            letters[&cell].contains(ours) || letters[&other].contains(theirs);
+                        if !blocked && !reached.contains(&other) {
+                            reached.push(other);
+                            queue.push_back(other);
+                        }
+                    }
+                }
+            }
+            assert_eq!(reached.len(), size * size * size * size);
+        }
+    }
+
+    #[test]
+    fn test_perfect_maze() {
+        let maze = Maze::generate(3, 42);
+        let passages: usize = maze
+            .open
+            .iter()
+            .map(|open| open.iter().filter(|&&o| o).count())
+            .sum();
+        assert_eq!(passages, maze.open.len() - 1);
+    }
+
+    #[test]
+    fn test_same_seed_same_maze() {
+        let a = Maze::generate(3, 1234);
+        let b = Maze::generate(3, 1234);
+        let c = Maze::generate(3, 4321);
+        assert!(a.cells() == b.cells());
+        assert!(a.cells() != c.cells());
+    }
+
+    #[test]
+    fn test_generated_level_parses() {
+        let level = Level::from_cells(Maze::generate(3, 7).cells()).unwrap();
+        let mut written = Vec::new();
+        level.write_to(&mut written).unwrap();
+        let text = String::from_utf8(written).unwrap();
+        assert!(text.parse::<Level>().is_ok());
+    }
+}
@@ -0,0 +1,945 @@
+use glium::glutin::VirtualKeyCode;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::str::FromStr;
+
+/// The bindings used when no other are given.
+const DEFAULT_BINDINGS: &str = include_str!("../bindings.cfg");
+
+/// Something the player can do, independently of the key that does it.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    MoveAna,
+    MoveKata,
+    /// While held, the mouse turns the player in the planes involving the w axis.
+    MouseAna,
+    RotateXYPlus,
+    RotateXYMinus,
+    RotateXZPlus,
+    RotateXZMinus,
+    RotateXWPlus,
+    RotateXWMinus,
+    RotateYZPlus,
+    RotateYZMinus,
+    RotateYWPlus,
+    RotateYWMinus,
+    RotateZWPlus,
+    RotateZWMinus,
+}
+
+/// The names of the actions in binding files.
+const ACTION_NAMES: [(&str, Action); 21] = [
+    ("move_forward", Action::MoveForward),
+    ("move_backward", Action::MoveBackward),
+    ("move_left", Action::MoveLeft),
+    ("move_right", Action::MoveRight),
+    ("move_up", Action::MoveUp),
+    ("move_down", Action::MoveDown),
+    ("move_ana", Action::MoveAna),
+    ("move_kata", Action::MoveKata),
+    ("mouse_ana", Action::MouseAna),
+    ("rotate_xy+", Action::RotateXYPlus),
+    ("rotate_xy-", Action::RotateXYMinus),
+    ("rotate_xz+", Action::RotateXZPlus),
+    ("rotate_xz-", Action::RotateXZMinus),
+    ("rotate_xw+", Action::RotateXWPlus),
+    ("rotate_xw-", Action::RotateXWMinus),
+    ("rotate_yz+", Action::RotateYZPlus),
+    ("rotate_yz-", Action::RotateYZMinus),
+    ("rotate_yw+", Action::RotateYWPlus),
+    ("rotate_yw-", Action::RotateYWMinus),
+    ("rotate_zw+", Action::RotateZWPlus),
+    ("rotate_zw-", Action::RotateZWMinus),
+];
+
+/// A direction of movement or a plane of rotation that can be controlled smoothly, for example
+/// with a gamepad stick. The keyboard drives the axes at full speed with pairs of actions.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum Axis {
+    MoveRight,
+    MoveUp,
+    MoveForward,
+    MoveAna,
+    RotateXY,
+    RotateXZ,
+    RotateXW,
+    RotateYZ,
+    RotateYW,
+    RotateZW,
+}
+
+impl Axis {
+    /// The actions moving along the axis in the positive and the negative direction.
+    fn actions(self) -> (Action, Action) {
+        match self {
+            Axis::MoveRight => (Action::MoveRight, Action::MoveLeft),
+            Axis::MoveUp => (Action::MoveUp, Action::MoveDown),
+            Axis::MoveForward => (Action::MoveForward, Action::MoveBackward),
+            Axis::MoveAna => (Action::MoveAna, Action::MoveKata),
+            Axis::RotateXY => (Action::RotateXYPlus, Action::RotateXYMinus),
+            Axis::RotateXZ => (Action::RotateXZPlus, Action::RotateXZMinus),
+            Axis::RotateXW => (Action::RotateXWPlus, Action::RotateXWMinus),
+            Axis::RotateYZ => (Action::RotateYZPlus, Action::RotateYZMinus),
+            Axis::RotateYW => (Action::RotateYWPlus, Action::RotateYWMinus),
+            Axis::RotateZW => (Action::RotateZWPlus, Action::RotateZWMinus),
+        }
+    }
+}
+
+/// The names of the axes in binding files.
+const AXIS_NAMES: [(&str, Axis); 10] = [
+    ("move_right", Axis::MoveRight),
+    ("move_up", Axis::MoveUp),
+    ("move_forward", Axis::MoveForward),
+    ("move_ana", Axis::MoveAna),
+    ("rotate_xy", Axis::RotateXY),
+    ("rotate_xz", Axis::RotateXZ),
+    ("rotate_xw", Axis::RotateXW),
+    ("rotate_yz", Axis::RotateYZ),
+    ("rotate_yw", Axis::RotateYW),
+    ("rotate_zw", Axis::RotateZW),
+];
+
+/// How the position of a device axis, from -1 to 1, translates into speed along a game axis.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AxisResponse {
+    /// Positions closer to the center than this are ignored, so that a stick at rest doesn't
+    /// make the player drift.
+    pub dead_zone: f64,
+    /// The exponent of the response curve; above 1 gives finer control near the center.
+    pub curve: f64,
+    /// The speed at full deflection, relative to the keyboard speed.
+    pub sensitivity: f64,
+    pub invert: bool,
+}
+
+impl Default for AxisResponse {
+    fn default() -> AxisResponse {
+        AxisResponse {
+            dead_zone: 0.15,
+            curve: 2.0,
+            sensitivity: 1.0,
+            invert: false,
+        }
+    }
+}
+
+impl AxisResponse {
+    pub fn apply(&self, position: f64) -> f64 {
+        let magnitude = position.abs().min(1.0);
+        if magnitude <= self.dead_zone {
+            return 0.0;
+        }
+        // rescale what's left outside of the dead zone to 0..1, so that the speed doesn't jump
+        let magnitude = (magnitude - self.dead_zone) / (1.0 - self.dead_zone);
+        let speed = magnitude.powf(self.curve) * self.sensitivity * position.signum();
+        if self.invert {
+            -speed
+        } else {
+            speed
+        }
+    }
+}
+
+/// Turning the player by moving the mouse.
+///
+/// Horizontal motion turns left and right within the visible slice (the same as `rotate_yw`),
+/// and vertical motion pitches up and down (`rotate_xw`). While `Action::MouseAna` is active,
+/// the mouse turns towards the w axis instead: horizontally with `rotate_yz` and vertically with
+/// `rotate_xz`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MouseLook {
+    pub enabled: bool,
+    /// Degrees per pixel of mouse motion.
+    pub sensitivity: f64,
+    pub invert_x: bool,
+    pub invert_y: bool,
+}
+
+impl Default for MouseLook {
+    fn default() -> MouseLook {
+        MouseLook {
+            enabled: false,
+            sensitivity: 0.15,
+            invert_x: false,
+            invert_y: false,
+        }
+    }
+}
+
+/// The keys that can be bound; they are named in binding files as in `VirtualKeyCode`. The zoom
+/// keys (+/-, PageUp/PageDown) and F3 are left out, as the game always handles them itself.
+const BINDABLE_KEYS: [VirtualKeyCode; 85] = [
+    VirtualKeyCode::A,
+    VirtualKeyCode::B,
+    VirtualKeyCode::C,
+    VirtualKeyCode::D,
+    VirtualKeyCode::E,
+    VirtualKeyCode::F,
+    VirtualKeyCode::G,
+    VirtualKeyCode::H,
+    VirtualKeyCode::I,
+    VirtualKeyCode::J,
+    VirtualKeyCode::K,
+    VirtualKeyCode::L,
+    VirtualKeyCode::M,
+    VirtualKeyCode::N,
+    VirtualKeyCode::O,
+    VirtualKeyCode::P,
+    VirtualKeyCode::Q,
+    VirtualKeyCode::R,
+    VirtualKeyCode::S,
+    VirtualKeyCode::T,
+    VirtualKeyCode::U,
+    VirtualKeyCode::V,
+    VirtualKeyCode::W,
+    VirtualKeyCode::X,
+    VirtualKeyCode::Y,
+    VirtualKeyCode::Z,
+    VirtualKeyCode::Key0,
+    VirtualKeyCode::Key1,
+    VirtualKeyCode::Key2,
+    VirtualKeyCode::Key3,
+    VirtualKeyCode::Key4,
+    VirtualKeyCode::Key5,
+    VirtualKeyCode::Key6,
+    VirtualKeyCode::Key7,
+    VirtualKeyCode::Key8,
+    VirtualKeyCode::Key9,
+    VirtualKeyCode::F1,
+    VirtualKeyCode::F2,
+    VirtualKeyCode::F4,
+    VirtualKeyCode::F5,
+    VirtualKeyCode::F6,
+    VirtualKeyCode::F7,
+    VirtualKeyCode::F8,
+    VirtualKeyCode::F9,
+    VirtualKeyCode::F10,
+    VirtualKeyCode::F11,
+    VirtualKeyCode::F12,
+    VirtualKeyCode::Left,
+    VirtualKeyCode::Right,
+    VirtualKeyCode::Up,
+    VirtualKeyCode::Down,
+    VirtualKeyCode::Space,
+    VirtualKeyCode::Return,
+    VirtualKeyCode::Tab,
+    VirtualKeyCode::Back,
+    VirtualKeyCode::Insert,
+    VirtualKeyCode::Delete,
+    VirtualKeyCode::Home,
+    VirtualKeyCode::End,
+    VirtualKeyCode::LShift,
+    VirtualKeyCode::RShift,
+    VirtualKeyCode::LControl,
+    VirtualKeyCode::RControl,
+    VirtualKeyCode::LAlt,
+    VirtualKeyCode::RAlt,
+    VirtualKeyCode::Numpad0,
+    VirtualKeyCode::Numpad1,
+    VirtualKeyCode::Numpad2,
+    VirtualKeyCode::Numpad3,
+    VirtualKeyCode::Numpad4,
+    VirtualKeyCode::Numpad5,
+    VirtualKeyCode::Numpad6,
+    VirtualKeyCode::Numpad7,
+    VirtualKeyCode::Numpad8,
+    VirtualKeyCode::Numpad9,
+    VirtualKeyCode::Multiply,
+    VirtualKeyCode::Divide,
+    VirtualKeyCode::Comma,
+    VirtualKeyCode::Period,
+    VirtualKeyCode::Semicolon,
+    VirtualKeyCode::Apostrophe,
+    VirtualKeyCode::Slash,
+    VirtualKeyCode::Backslash,
+    VirtualKeyCode::LBracket,
+    VirtualKeyCode::RBracket,
+];
+
+/// Everything that can go wrong while reading a binding file.
+///
+/// Line numbers are 1-based.
+#[derive(Debug)]
+pub enum BindingError {
+    Io(io::Error),
+    Syntax { line: usize, text: String },
+    UnknownKey { line: usize, key: String },
+    UnknownAction { line: usize, action: String },
+    DuplicateKey { line: usize, key: String },
+    BadOption { line: usize, option: String },
+}
+
+impl Display for BindingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BindingError::Io(ref err) => write!(f, "I/O error: {}", err),
+            BindingError::Syntax { line, ref text } => {
+                write!(
+                    f,
+                    "line {}: expected `KEY = ACTION`, found `{}`",
+                    line, text
+                )
+            }
+            BindingError::UnknownKey { line, ref key } => {
+                write!(f, "line {}: unknown key `{}`", line, key)
+            }
+            BindingError::UnknownAction { line, ref action } => {
+                write!(f, "line {}: unknown action `{}`", line, action)
+            }
+            BindingError::DuplicateKey { line, ref key } => {
+                write!(f, "line {}: key `{}` is already bound", line, key)
+            }
+            BindingError::BadOption { line, ref option } => {
+                write!(f, "line {}: invalid axis option `{}`", line, option)
+            }
+        }
+    }
+}
+
+impl Error for BindingError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            BindingError::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BindingError {
+    fn from(err: io::Error) -> BindingError {
+        BindingError::Io(err)
+    }
+}
+
+pub struct KeyboardState {
+    pressed_keys: HashSet<VirtualKeyCode>,
+}
+
+impl KeyboardState {
+    pub fn new() -> KeyboardState {
+        KeyboardState {
+            pressed_keys: HashSet::new(),
+        }
+    }
+
+    pub fn pressed(&mut self, key: VirtualKeyCode) {
+        self.pressed_keys.insert(key);
+    }
+
+    pub fn released(&mut self, key: VirtualKeyCode) {
+        self.pressed_keys.remove(&key);
+    }
+
+    pub fn is_pressed(&self, key: VirtualKeyCode) -> bool {
+        self.pressed_keys.contains(&key)
+    }
+}
+
+/// Mouse motion since the last frame, in pixels.
+#[derive(Default)]
+pub struct MouseState {
+    position: Option<(i32, i32)>,
+    motion: (i32, i32),
+}
+
+impl MouseState {
+    pub fn new() -> MouseState {
+        Default::default()
+    }
+
+    pub fn moved(&mut self, x: i32, y: i32) {
+        if let Some((last_x, last_y)) = self.position {
+            self.motion.0 += x - last_x;
+            self.motion.1 += y - last_y;
+        }
+        self.position = Some((x, y));
+    }
+
+    /// Records that the cursor was moved by the game, so that it doesn't count as motion.
+    pub fn warped(&mut self, x: i32, y: i32) {
+        self.position = Some((x, y));
+    }
+
+    pub fn motion(&self) -> (i32, i32) {
+        self.motion
+    }
+
+    pub fn clear_motion(&mut self) {
+        self.motion = (0, 0);
+    }
+}
+
+/// The positions of the axes of analog devices, from -1 to 1, by axis number.
+#[derive(Default)]
+pub struct AnalogState {
+    positions: HashMap<u8, f64>,
+}
+
+impl AnalogState {
+    pub fn new() -> AnalogState {
+        Default::default()
+    }
+
+    pub fn set(&mut self, axis: u8, position: f64) {
+        self.positions.insert(axis, position.max(-1.0).min(1.0));
+    }
+
+    pub fn position(&self, axis: u8) -> f64 {
+        self.positions.get(&axis).cloned().unwrap_or(0.0)
+    }
+}
+
+/// The actions the player is performing at the moment.
+#[derive(Clone, Default)]
+pub struct ActionState {
+    active: HashSet<Action>,
+    axes: HashMap<Axis, f64>,
+    turns: HashMap<Axis, f64>,
+}
+
+impl ActionState {
+    pub fn new() -> ActionState {
+        Default::default()
+    }
+
+    pub fn set(&mut self, action: Action, active: bool) {
+        if active {
+            self.active.insert(action);
+        } else {
+            self.active.remove(&action);
+        }
+    }
+
+    pub fn is_active(&self, action: Action) -> bool {
+        self.active.contains(&action)
+    }
+
+    /// Sets the analog speed along an axis, on top of the one given by the actions.
+    pub fn set_axis(&mut self, axis: Axis, speed: f64) {
+        self.axes.insert(axis, speed);
+    }
+
+    /// The speed along the axis: 1 or -1 for the keyboard, anything in between for analog
+    /// devices, and possibly more with an increased sensitivity.
+    pub fn axis(&self, axis: Axis) -> f64 {
+        let (plus, minus) = axis.actions();
+        let mut speed = self.axes.get(&axis).cloned().unwrap_or(0.0);
+        if self.is_active(plus) {
+            speed += 1.0;
+        }
+        if self.is_active(minus) {
+            speed -= 1.0;
+        }
+        speed
+    }
+
+    /// Adds a rotation by a given angle around an axis, independent of the frame time.
+    pub fn turn(&mut self, axis: Axis, angle: f64) {
+        *self.turns.entry(axis).or_insert(0.0) += angle;
+    }
+
+    pub fn turned(&self, axis: Axis) -> f64 {
+        self.turns.get(&axis).cloned().unwrap_or(0.0)
+    }
+
+    /// Adds the turns from `other`, for turns that had to wait.
+    pub fn add_turns(&mut self, other: &ActionState) {
+        for (&axis, &angle) in other.turns.iter() {
+            self.turn(axis, angle);
+        }
+    }
+
+    /// The same actions without the turns, for when they have already been applied.
+    pub fn without_turns(&self) -> ActionState {
+        ActionState {
+            turns: HashMap::new(),
+            ..self.clone()
+        }
+    }
+}
+
+/// Writes the state as space-separated words: the names of the active actions, then
+/// `axis:NAME=SPEED` for analog speeds and `turn:NAME=ANGLE` for turns. Numbers are written so
+/// that reading them back gives exactly the same values.
+impl Display for ActionState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut words = Vec::new();
+        for &(name, action) in ACTION_NAMES.iter() {
+            if self.is_active(action) {
+                words.push(name.to_owned());
+            }
+        }
+        for &(prefix, values) in [("axis", &self.axes), ("turn", &self.turns)].iter() {
+            for &(name, axis) in AXIS_NAMES.iter() {
+                match values.get(&axis) {
+                    Some(&value) if value != 0.0 => {
+                        words.push(format!("{}:{}={}", prefix, name, value))
+                    }
+                    _ => (),
+                }
+            }
+        }
+        write!(f, "{}", words.join(" "))
+    }
+}
+
+impl FromStr for ActionState {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<ActionState, String> {
+        let mut state = ActionState::new();
+        for word in text.split_whitespace() {
+            let bad_word = || format!("invalid action `{}`", word);
+            if let Some(pos) = word.find(':') {
+                let (kind, rest) = (&word[..pos], &word[pos + 1..]);
+                let pos = rest.find('=').ok_or_else(bad_word)?;
+                let axis = AXIS_NAMES
+                    .iter()
+                    .find(|&&(name, _)| name == &rest[..pos])
+                    .map(|&(_, axis)| axis)
+                    .ok_or_else(bad_word)?;
+                let value = rest[pos + 1..].parse().map_err(|_| bad_word())?;
+                match kind {
+                    "axis" => state.set_axis(axis, value),
+                    "turn" => state.turn(axis, value),
+                    _ => return Err(bad_word()),
+                }
+            } else {
+                let action = ACTION_NAMES
+                    .iter()
+                    .find(|&&(name, _)| name == word)
+                    .map(|&(_, action)| action)
+                    .ok_or_else(bad_word)?;
+                state.set(action, true);
+            }
+        }
+        Ok(state)
+    }
+}
+
+/// Which key triggers which action, read from a file like this:
+///
+/// ```text
+/// # comment
+/// W = move_forward
+/// Up = move_forward
+/// T = rotate_xw+
+/// axis0 = move_right dead_zone=0.1 curve=1.5
+/// axis1 = move_forward invert
+/// mouse = look sensitivity=0.1 invert_y
+/// ```
+///
+/// Lines starting with `axisN` bind the axis number N of an analog device. They name a game axis
+/// and optionally change its `AxisResponse`. The `mouse` line enables `MouseLook` and sets it up.
+/// A binding file replaces the default bindings entirely.
+pub struct Bindings {
+    keys: HashMap<VirtualKeyCode, Action>,
+    axes: HashMap<u8, (Axis, AxisResponse)>,
+    mouse: MouseLook,
+}
+
+impl Bindings {
+    pub fn from_file<P: AsRef<Path>>(file_name: P) -> Result<Bindings, BindingError> {
+        let f = File::open(file_name)?;
+        Bindings::from_reader(BufReader::new(f))
+    }
+
+    pub fn from_reader<R: BufRead>(reader: R) -> Result<Bindings, BindingError> {
+        let mut keys = HashMap::new();
+        let mut axes = HashMap::new();
+        let mut mouse = None;
+        for (index, line) in reader.lines().enumerate() {
+            let line_num = index + 1;
+            let line = line?;
+            let text = line.trim();
+            if text.is_empty() || text.starts_with('#') {
+                continue;
+            }
+
+            let (key, action) = match text.find('=') {
+                Some(pos) => (text[..pos].trim(), text[pos + 1..].trim()),
+                None => {
+                    return Err(BindingError::Syntax {
+                        line: line_num,
+                        text: text.to_owned(),
+                    })
+                }
+            };
+            if let Some(number) = parse_device_axis(key) {
+                let binding = parse_axis_binding(action, line_num)?;
+                if axes.insert(number, binding).is_some() {
+                    return Err(BindingError::DuplicateKey {
+                        line: line_num,
+                        key: key.to_owned(),
+                    });
+                }
+                continue;
+            }
+            if key == "mouse" {
+                if mouse.is_some() {
+                    return Err(BindingError::DuplicateKey {
+                        line: line_num,
+                        key: key.to_owned(),
+                    });
+                }
+                mouse = Some(parse_mouse_look(action, line_num)?);
+                continue;
+            }
+            let code = parse_key(key).ok_or_else(|| BindingError::UnknownKey {
+                line: line_num,
+                key: key.to_owned(),
+            })?;
+            let action = ACTION_NAMES
+                .iter()
+                .find(|&&(name, _)| name == action)
+                .map(|&(_, action)| action)
+                .ok_or_else(|| BindingError::UnknownAction {
+                    line: line_num,
+                    action: action.to_owned(),
+                })?;
+            if keys.insert(code, action).is_some() {
+                return Err(BindingError::DuplicateKey {
+                    line: line_num,
+                    key: key.to_owned(),
+                });
+            }
+        }
+        Ok(Bindings {
+            keys: keys,
+            axes: axes,
+            mouse: mouse.unwrap_or_default(),
+        })
+    }
+
+    pub fn mouse_look(&self) -> MouseLook {
+        self.mouse
+    }
+
+    pub fn enable_mouse_look(&mut self) {
+        self.mouse.enabled = true;
+    }
+
+    /// The actions triggered by the keys pressed, the analog axes and the mouse moved at the
+    /// moment.
+    pub fn actions(
+        &self,
+        keyboard: &KeyboardState,
+        analog: &AnalogState,
+        mouse: &MouseState,
+    ) -> ActionState {
+        let mut state = ActionState::new();
+        for (&key, &action) in self.keys.iter() {
+            if keyboard.is_pressed(key) {
+                state.set(action, true);
+            }
+        }
+        let mut speeds = HashMap::new();
+        for (&number, &(axis, response)) in self.axes.iter() {
+            *speeds.entry(axis).or_insert(0.0) += response.apply(analog.position(number));
+        }
+        for (axis, speed) in speeds {
+            state.set_axis(axis, speed);
+        }
+        if self.mouse.enabled {
+            let (horizontal, vertical) = if state.is_active(Action::MouseAna) {
+                (Axis::RotateYZ, Axis::RotateXZ)
+            } else {
+                (Axis::RotateYW, Axis::RotateXW)
+            };
+            let sign = |invert| if invert { -1.0 } else { 1.0 };
+            let (dx, dy) = mouse.motion();
+            let angle = self.mouse.sensitivity.to_radians();
+            state.turn(horizontal, dx as f64 * angle * sign(self.mouse.invert_x));
+            state.turn(vertical, dy as f64 * angle * sign(self.mouse.invert_y));
+        }
+        state
+    }
+}
+
+impl Default for Bindings {
+    fn default() -> Bindings {
+        Bindings::from_reader(DEFAULT_BINDINGS.as_bytes()).expect("the default bindings are valid")
+    }
+}
+
+/// The number N of an `axisN` key.
+fn parse_device_axis(name: &str) -> Option<u8> {
+    if name.len() > 4 && name[..4].eq_ignore_ascii_case("axis") {
+        name[4..].parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Parses `AXIS [OPTION...]`, the binding of a device axis.
+fn parse_axis_binding(text: &str, line: usize) -> Result<(Axis, AxisResponse), BindingError> {
+    let mut words = text.split_whitespace();
+    let name = words.next().unwrap_or("");
+    let axis = AXIS_NAMES
+        .iter()
+        .find(|&&(axis_name, _)| axis_name == name)
+        .map(|&(_, axis)| axis)
+        .ok_or_else(|| BindingError::UnknownAction {
+            line: line,
+            action: name.to_owned(),
+        })?;
+
+    let mut response = AxisResponse::default();
+    for option in words {
+        let bad_option = || BindingError::BadOption {
+            line: line,
+            option: option.to_owned(),
+        };
+        if option == "invert" {
+            response.invert = true;
+            continue;
+        }
+        let (key, value) = match option.find('=') {
+            Some(pos) => (&option[..pos], &option[pos + 1..]),
+            None => return Err(bad_option()),
+        };
+        let value: f64 = value.parse().map_err(|_| bad_option())?;
+        match key {
+            "dead_zone" if value >= 0.0 && value < 1.0 => response.dead_zone = value,
+            "curve" if value > 0.0 => response.curve = value,
+            "sensitivity" if value > 0.0 => response.sensitivity = value,
+            _ => return Err(bad_option()),
+        }
+    }
+    Ok((axis, response))
+}
+
+/// Parses `look [OPTION...]`, the setup of mouse-look.
+fn parse_mouse_look(text: &str, line: usize) -> Result<MouseLook, BindingError> {
+    let mut words = text.split_whitespace();
+    match words.next() {
+        Some("look") => (),
+        other => {
+            return Err(BindingError::UnknownAction {
+                line: line,
+                action: other.unwrap_or("").to_owned(),
+            })
+        }
+    }
+
+    let mut mouse = MouseLook {
+        enabled: true,
+        ..Default::default()
+    };
+    for option in words {
+        let bad_option = || BindingError::BadOption {
+            line: line,
+            option: option.to_owned(),
+        };
+        match option {
+            "invert_x" => mouse.invert_x = true,
+            "invert_y" => mouse.invert_y = true,
+            _ if option.starts_with("sensitivity=") => {
+                match option["sensitivity=".len()..].parse() {
+                    Ok(sensitivity) if sensitivity > 0.0 => mouse.sensitivity = sensitivity,
+                    _ => return Err(bad_option()),
+                }
+            }
+            _ => return Err(bad_option()),
+        }
+    }
+    Ok(mouse)
+}
+
+fn parse_key(name: &str) -> Option<VirtualKeyCode> {
+    BINDABLE_KEYS
+        .iter()
+        .find(|key| format!("{:?}", key).eq_ignore_ascii_case(name))
+        .cloned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        Action, AnalogState, Axis, AxisResponse, BindingError, Bindings, KeyboardState, MouseState,
+    };
+    use glium::glutin::VirtualKeyCode;
+
+    #[test]
+    fn test_default_bindings() {
+        let bindings = Bindings::default();
+        let mut keyboard = KeyboardState::new();
+        keyboard.pressed(VirtualKeyCode::W);
+        keyboard.pressed(VirtualKeyCode::R);
+        keyboard.pressed(VirtualKeyCode::P);
+        let actions = bindings.actions(&keyboard, &AnalogState::new(), &MouseState::new());
+        assert!(actions.is_active(Action::MoveForward));
+        assert!(actions.is_active(Action::RotateYWMinus));
+        assert_eq!(actions.axis(Axis::MoveForward), 1.0);
+        assert_eq!(actions.axis(Axis::RotateYW), -1.0);
+        assert!(!actions.is_active(Action::MoveBackward));
+        assert!(!actions.is_active(Action::RotateYWPlus));
+    }
+
+    #[test]
+    fn test_custom_bindings() {
+        let bindings = Bindings::from_reader(
+            "# arrows and the numeric keypad\n\
+             Up = move_forward\n\
+             numpad8 = move_forward\n\
+             LShift = rotate_xw+\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let mut keyboard = KeyboardState::new();
+        keyboard.pressed(VirtualKeyCode::W);
+        let (analog, mouse) = (AnalogState::new(), MouseState::new());
+        assert!(!bindings
+            .actions(&keyboard, &analog, &mouse)
+            .is_active(Action::MoveForward));
+
+        keyboard.pressed(VirtualKeyCode::Up);
+        keyboard.pressed(VirtualKeyCode::Numpad8);
+        keyboard.released(VirtualKeyCode::Up);
+        let actions = bindings.actions(&keyboard, &analog, &mouse);
+        assert!(actions.is_active(Action::MoveForward));
+        assert!(!actions.is_active(Action::RotateXWPlus));
+    }
+
+    #[test]
+    fn test_errors() {
+        let parse = |text: &str| Bindings::from_reader(text.as_bytes()).err();
+        match parse("W move_forward\n") {
+            Some(BindingError::Syntax { line: 1, .. }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match parse("W = move_forward\nWW = move_left\n") {
+            Some(BindingError::UnknownKey { line: 2, ref key }) if key == "WW" => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match parse("W = move_forward\nPageUp = move_up\n") {
+            Some(BindingError::UnknownKey { line: 2, ref key }) if key == "PageUp" => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match parse("\nW = jump\n") {
+            Some(BindingError::UnknownAction {
+                line: 2,
+                ref action,
+            }) if action == "jump" => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match parse("W = move_forward\nw = move_left\n") {
+            Some(BindingError::DuplicateKey { line: 2, .. }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match parse("axis0 = rotate_xw+\n") {
+            Some(BindingError::UnknownAction { line: 1, .. }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match parse("mouse = look sensitivity=-1\n") {
+            Some(BindingError::BadOption { line: 1, .. }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match parse("axis0 = rotate_xw dead_zone=1.5\n") {
+            Some(BindingError::BadOption {
+                line: 1,
+                ref option,
+            }) if option == "dead_zone=1.5" => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_axis_response() {
+        let response = AxisResponse::default();
+        assert_eq!(response.apply(0.1), 0.0);
+        assert_eq!(response.apply(-0.15), 0.0);
+        assert_eq!(response.apply(1.0), 1.0);
+        assert_eq!(response.apply(-2.0), -1.0);
+        // halfway between the dead zone and the edge, on a quadratic curve
+        assert!((response.apply(-0.575) + 0.25).abs() < 1e-9);
+
+        let response = AxisResponse {
+            dead_zone: 0.0,
+            curve: 1.0,
+            sensitivity: 2.0,
+            invert: true,
+        };
+        assert!((response.apply(0.3) + 0.6).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_analog_bindings() {
+        let bindings = Bindings::from_reader(
+            "W = move_forward\n\
+             axis1 = move_forward invert\n\
+             axis3 = rotate_yw curve=1 dead_zone=0\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let mut analog = AnalogState::new();
+        analog.set(1, -1.0);
+        analog.set(3, 0.5);
+        let mut keyboard = KeyboardState::new();
+        let mouse = MouseState::new();
+        let actions = bindings.actions(&keyboard, &analog, &mouse);
+        assert_eq!(actions.axis(Axis::MoveForward), 1.0);
+        assert_eq!(actions.axis(Axis::RotateYW), 0.5);
+        assert!(!actions.is_active(Action::MoveForward));
+
+        // the keyboard and the stick add up
+        keyboard.pressed(VirtualKeyCode::W);
+        let actions = bindings.actions(&keyboard, &analog, &mouse);
+        assert_eq!(actions.axis(Axis::MoveForward), 2.0);
+    }
+
+    #[test]
+    fn test_mouse_look() {
+        let bindings = Bindings::from_reader(
+            "LShift = mouse_ana\n\
+             mouse = look sensitivity=0.1 invert_y\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let mut keyboard = KeyboardState::new();
+        let analog = AnalogState::new();
+        let mut mouse = MouseState::new();
+        mouse.moved(100, 100);
+        mouse.moved(110, 95);
+        mouse.moved(120, 80);
+        assert_eq!(mouse.motion(), (20, -20));
+
+        let degree = 1f64.to_radians();
+        let actions = bindings.actions(&keyboard, &analog, &mouse);
+        assert!((actions.turned(Axis::RotateYW) - 2.0 * degree).abs() < 1e-9);
+        assert!((actions.turned(Axis::RotateXW) - 2.0 * degree).abs() < 1e-9);
+        assert_eq!(actions.turned(Axis::RotateYZ), 0.0);
+
+        keyboard.pressed(VirtualKeyCode::LShift);
+        let actions = bindings.actions(&keyboard, &analog, &mouse);
+        assert_eq!(actions.turned(Axis::RotateYW), 0.0);
+        assert!((actions.turned(Axis::RotateYZ) - 2.0 * degree).abs() < 1e-9);
+        assert!((actions.turned(Axis::RotateXZ) - 2.0 * degree).abs() < 1e-9);
+
+        // moving the cursor back to the center doesn't turn the player
+        mouse.clear_motion();
+        mouse.warped(100, 100);
+        assert_eq!(mouse.motion(), (0, 0));
+
+        // off unless enabled
+        let mut bindings = Bindings::default();
+        keyboard.released(VirtualKeyCode::LShift);
+        mouse.moved(150, 100);
+        let actions = bindings.actions(&keyboard, &analog, &mouse);
+        assert_eq!(actions.turned(Axis::RotateYW), 0.0);
+        bindings.enable_mouse_look();
+        let actions = bindings.actions(&keyboard, &analog, &mouse);
+        assert!((actions.turned(Axis::RotateYW) - 7.5 * degree).abs() < 1e-9);
+    }
+}
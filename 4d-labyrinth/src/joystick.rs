@@ -0,0 +1,159 @@
+use super::input::AnalogState;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+/// The device tried when none is given.
+pub const DEFAULT_DEVICE: &str = "/dev/input/js0";
+
+/// The size of an event of the Linux joystick interface: a 32-bit timestamp in milliseconds,
+/// a signed 16-bit value, an 8-bit type and an 8-bit axis or button number.
+const EVENT_SIZE: usize = 8;
+const EVENT_AXIS: u8 = 0x02;
+/// Marks the events describing the initial state of the device.
+const EVENT_INIT: u8 = 0x80;
+
+/// A change of the position of a device axis.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AxisEvent {
+    pub axis: u8,
+    pub position: f64,
+}
+
+/// Reads events in the format of the Linux joystick interface until the next axis event,
+/// skipping buttons. Returns `None` at the end of the stream.
+pub fn read_axis_event<R: Read>(reader: &mut R) -> io::Result<Option<AxisEvent>> {
+    let mut event = [0u8; EVENT_SIZE];
+    loop {
+        match reader.read_exact(&mut event) {
+            Ok(()) => (),
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        }
+        let value = i16::from_le_bytes([event[4], event[5]]);
+        if event[6] & !EVENT_INIT == EVENT_AXIS {
+            return Ok(Some(AxisEvent {
+                axis: event[7],
+                position: (value as f64 / i16::max_value() as f64).max(-1.0),
+            }));
+        }
+    }
+}
+
+/// A gamepad or a joystick, read through the Linux joystick interface.
+///
+/// Reads block until the device reports a change, so they happen on a separate thread, and
+/// the game picks up the changes once per frame.
+pub struct Joystick {
+    events: Receiver<AxisEvent>,
+}
+
+impl Joystick {
+    pub fn open<P: AsRef<Path>>(device: P) -> io::Result<Joystick> {
+        let mut file = File::open(device)?;
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            while let Ok(Some(event)) = read_axis_event(&mut file) {
+                if sender.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Joystick { events: receiver })
+    }
+
+    /// Applies the changes reported since the last update.
+    pub fn update(&self, analog: &mut AnalogState) {
+        while let Ok(event) = self.events.try_recv() {
+            analog.set(event.axis, event.position);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::input::{AnalogState, Bindings, KeyboardState, MouseState};
+    use super::super::objects::{AdditionalAction, Player};
+    use super::{read_axis_event, EVENT_AXIS, EVENT_INIT};
+    use graph4d::geometry::Vector;
+
+    const EVENT_BUTTON: u8 = 0x01;
+
+    fn event(time: u32, value: i16, kind: u8, number: u8) -> Vec<u8> {
+        let mut bytes = time.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&value.to_le_bytes());
+        bytes.push(kind);
+        bytes.push(number);
+        bytes
+    }
+
+    /// Plays a recorded stream of events into the analog state.
+    fn play(stream: &[u8], analog: &mut AnalogState) {
+        let mut reader = stream;
+        while let Some(event) = read_axis_event(&mut reader).unwrap() {
+            analog.set(event.axis, event.position);
+        }
+    }
+
+    #[test]
+    fn test_read_events() {
+        let stream: Vec<u8> = vec![
+            event(0, 0, EVENT_AXIS | EVENT_INIT, 0),
+            event(10, 1, EVENT_BUTTON, 0),
+            event(20, -32768, EVENT_AXIS, 1),
+            event(30, 16384, EVENT_AXIS, 0),
+        ]
+        .concat();
+        let mut reader = &stream[..];
+        let positions: Vec<(u8, f64)> = (0..3)
+            .map(|_| read_axis_event(&mut reader).unwrap().unwrap())
+            .map(|event| (event.axis, event.position))
+            .collect();
+        assert_eq!(positions[0], (0, 0.0));
+        assert_eq!(positions[1], (1, -1.0));
+        assert_eq!(positions[2].0, 0);
+        assert!((positions[2].1 - 0.5).abs() < 1e-4);
+        assert!(read_axis_event(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_proportional_movement() {
+        let bindings = Bindings::default();
+        let keyboard = KeyboardState::new();
+        let mouse = MouseState::new();
+        let mut analog = AnalogState::new();
+        let mut player = Player::new();
+        let start = player.position();
+
+        // a stick at rest, slightly off the center
+        play(&event(0, 2000, EVENT_AXIS, 1), &mut analog);
+        let actions = bindings.actions(&keyboard, &analog, &mouse);
+        match player.handle_input(&actions, 1.0) {
+            AdditionalAction::None => (),
+            _ => panic!("a stick in the dead zone shouldn't move the player"),
+        }
+
+        // the left stick pushed all the way forwards, then back to half way
+        let stream = [
+            event(100, -16000, EVENT_AXIS, 1),
+            event(116, -32767, EVENT_AXIS, 1),
+        ]
+        .concat();
+        play(&stream, &mut analog);
+        let full = match player.handle_input(&bindings.actions(&keyboard, &analog, &mouse), 1.0) {
+            AdditionalAction::MoveTo(position) => position - start,
+            _ => panic!("the player should move"),
+        };
+        assert!(full == Vector::new(0.0, 0.0, 3.2, 0.0));
+
+        play(&event(200, -18841, EVENT_AXIS, 1), &mut analog);
+        let half = match player.handle_input(&bindings.actions(&keyboard, &analog, &mouse), 1.0) {
+            AdditionalAction::MoveTo(position) => position - start,
+            _ => panic!("the player should move"),
+        };
+        // half way outside of the dead zone, on a quadratic curve
+        assert!(half == Vector::new(0.0, 0.0, 0.8, 0.0));
+    }
+}
@@ -1,102 +1,227 @@
 use super::objects::{AdditionalAction, Collidable, GameObject, Player, Target, Wall};
-use graph4d::geometry::Vector;
+use super::spatial::SpatialIndex;
+use graph4d::geometry::{Aabb, Hyperplane, Matrix, Vector};
 use regex::Regex;
-use std::fmt::Display;
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt::{self, Display};
 use std::fs::File;
-use std::io::{self, BufRead, BufReader, Result};
+use std::io::{self, BufRead, BufReader, Write};
 use std::iter::{once, Iterator};
 use std::path::Path;
+use std::str::FromStr;
 
 enum ReadState {
     NoLines,
-    OneLine(String),
-    TwoLines(String, String),
+    OneLine(usize, String),
+    TwoLines(usize, String, String),
 }
 
 enum LineResult {
     Target(Target),
     Walls(Vec<Wall>),
-    Error,
 }
 
-pub struct Level {
-    walls: Vec<Wall>,
-    target: Target,
-    player: Player,
+/// Everything that can go wrong while loading a level.
+///
+/// Line numbers are 1-based and refer to the line of the level file the error was found on.
+#[derive(Debug)]
+pub enum LevelError {
+    Io(io::Error),
+    BadCoordinate {
+        line: usize,
+        text: String,
+    },
+    UnknownWall {
+        line: usize,
+        text: String,
+        wall: char,
+    },
+    MissingTarget,
+    DuplicateTarget {
+        line: usize,
+        text: String,
+    },
+    PartialBlock {
+        line: usize,
+        text: String,
+    },
+    DuplicateDirective {
+        line: usize,
+        text: String,
+    },
+    BadOrientation {
+        line: usize,
+    },
+    StartOutside {
+        line: usize,
+    },
+    StartBlocked {
+        line: usize,
+    },
+    BadWall {
+        line: usize,
+        text: String,
+    },
+    MissingWalls {
+        line: usize,
+    },
 }
 
-impl Level {
-    pub fn from_file<P: AsRef<Path> + Display>(file_name: P) -> Result<Level> {
-        let f = File::open(file_name)?;
-        let reader = BufReader::new(&f);
-        let mut state = ReadState::NoLines;
-        let mut target = None;
-        let mut walls = Vec::new();
-
-        for line in reader.lines() {
-            match state {
-                ReadState::NoLines => state = ReadState::OneLine(line?),
-                ReadState::OneLine(s) => state = ReadState::TwoLines(s, line?),
-                ReadState::TwoLines(s1, s2) => {
-                    let s3 = line?;
-                    match Level::process_lines(s1, s2, s3) {
-                        LineResult::Target(t) => target = Some(t),
-                        LineResult::Walls(mut w) => walls.append(&mut w),
-                        LineResult::Error => {
-                            return Err(io::Error::new(
-                                io::ErrorKind::InvalidData,
-                                "Invalid level definition",
-                            ))
-                        }
-                    }
-                    state = ReadState::NoLines;
-                }
+impl Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LevelError::Io(ref err) => write!(f, "I/O error: {}", err),
+            LevelError::BadCoordinate { line, ref text } => write!(
+                f,
+                "line {}: expected four coordinates, found `{}`",
+                line, text
+            ),
+            LevelError::UnknownWall {
+                line,
+                ref text,
+                wall,
+            } => write!(
+                f,
+                "line {}: unknown wall `{}` in `{}` (expected one of xXyYzZwW, or T for the target)",
+                line, wall, text
+            ),
+            LevelError::MissingTarget => write!(f, "the level has no target block"),
+            LevelError::DuplicateTarget { line, ref text } => write!(
+                f,
+                "line {}: second target block `{}` (only one target is allowed)",
+                line, text
+            ),
+            LevelError::PartialBlock { line, ref text } => write!(
+                f,
+                "line {}: incomplete block starting with `{}` (expected two coordinate lines and a wall line)",
+                line, text
+            ),
+            LevelError::DuplicateDirective { line, ref text } => {
+                write!(f, "line {}: `{}` repeats an earlier directive", line, text)
+            }
+            LevelError::BadOrientation { line } => write!(
+                f,
+                "line {}: the right, up, front and ana directions aren't orthonormal or are mirrored",
+                line
+            ),
+            LevelError::StartOutside { line } => {
+                write!(f, "line {}: the start is outside of all the blocks", line)
             }
+            LevelError::StartBlocked { line } => {
+                write!(f, "line {}: the start is inside a wall", line)
+            }
+            LevelError::BadWall { line, ref text } => write!(
+                f,
+                "line {}: expected `wall`, a center, three extents and rotations like `xw 45`, found `{}`",
+                line, text
+            ),
+            LevelError::MissingWalls { line } => write!(
+                f,
+                "line {}: the block has no wall line (use `{}` for a block without walls)",
+                line, NO_WALLS
+            ),
         }
-        Ok(Level {
-            player: Player::new(),
-            target: target.unwrap(),
-            walls: walls,
-        })
     }
+}
 
-    fn process_lines(s1: String, s2: String, s3: String) -> LineResult {
-        let coord_line = Regex::new(r"^(?P<x>-?\d+(\.\d+)?)\s+(?P<y>-?\d+(\.\d+)?)\s+(?P<z>-?\d+(\.\d+)?)\s+(?P<w>-?\d+(\.\d+)?)").unwrap();
-        let cap1 = coord_line.captures(&s1).unwrap();
-        let cap2 = coord_line.captures(&s2).unwrap();
-        let (x1, y1, z1, w1): (f64, f64, f64, f64) = (
-            cap1.name("x").unwrap().parse().unwrap(),
-            cap1.name("y").unwrap().parse().unwrap(),
-            cap1.name("z").unwrap().parse().unwrap(),
-            cap1.name("w").unwrap().parse().unwrap(),
-        );
-        let (x2, y2, z2, w2): (f64, f64, f64, f64) = (
-            cap2.name("x").unwrap().parse().unwrap(),
-            cap2.name("y").unwrap().parse().unwrap(),
-            cap2.name("z").unwrap().parse().unwrap(),
-            cap2.name("w").unwrap().parse().unwrap(),
-        );
+impl Error for LevelError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            LevelError::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
 
-        let (x1, x2) = if x1 < x2 { (x1, x2) } else { (x2, x1) };
-        let (y1, y2) = if y1 < y2 { (y1, y2) } else { (y2, y1) };
-        let (z1, z2) = if z1 < z2 { (z1, z2) } else { (z2, z1) };
-        let (w1, w2) = if w1 < w2 { (w1, w2) } else { (w2, w1) };
-
-        if s3 == "T" {
-            return LineResult::Target(Target::new(
-                Vector::new(
-                    (x1 + x2) / 2.0,
-                    (y1 + y2) / 2.0,
-                    (z1 + z2) / 2.0,
-                    (w1 + w2) / 2.0,
-                ),
-                x2 - x1,
-            ));
+impl From<io::Error> for LevelError {
+    fn from(err: io::Error) -> LevelError {
+        LevelError::Io(err)
+    }
+}
+
+/// What a block of the level file describes: walls on some of the faces of the box, or the
+/// target filling the whole box. A block without any walls has `-` for its wall line.
+#[derive(Clone, PartialEq)]
+pub enum CellContents {
+    Walls(String),
+    Target,
+}
+
+/// The wall line of a block without any walls.
+const NO_WALLS: &str = "-";
+
+/// A single block of the level file - two opposite corners of a box and its contents.
+///
+/// The corners are kept in the order they were written, so a saved level has the same geometry
+/// as the one it was loaded from - though not necessarily the same text, as the numbers are
+/// written back in their shortest form.
+#[derive(Clone, PartialEq)]
+pub struct Cell {
+    corner1: Vector,
+    corner2: Vector,
+    contents: CellContents,
+}
+
+impl Cell {
+    pub fn new(corner1: Vector, corner2: Vector, contents: CellContents) -> Cell {
+        Cell {
+            corner1: corner1,
+            corner2: corner2,
+            contents: contents,
         }
+    }
+
+    pub fn contents(&self) -> &CellContents {
+        &self.contents
+    }
+
+    /// The corner of the box with the lowest coordinates.
+    pub fn min(&self) -> Vector {
+        Vector::new(
+            self.corner1.x().min(self.corner2.x()),
+            self.corner1.y().min(self.corner2.y()),
+            self.corner1.z().min(self.corner2.z()),
+            self.corner1.w().min(self.corner2.w()),
+        )
+    }
+
+    /// The corner of the box with the highest coordinates.
+    pub fn max(&self) -> Vector {
+        Vector::new(
+            self.corner1.x().max(self.corner2.x()),
+            self.corner1.y().max(self.corner2.y()),
+            self.corner1.z().max(self.corner2.z()),
+            self.corner1.w().max(self.corner2.w()),
+        )
+    }
+
+    /// Creates the game objects described by the cell. Returns the offending character if the
+    /// wall line contains anything other than wall letters.
+    fn objects(&self) -> Result<LineResult, char> {
+        let min = self.min();
+        let max = self.max();
+        let (x1, y1, z1, w1) = (min.x(), min.y(), min.z(), min.w());
+        let (x2, y2, z2, w2) = (max.x(), max.y(), max.z(), max.w());
+
+        let letters = match self.contents {
+            CellContents::Target => {
+                return Ok(LineResult::Target(Target::new(
+                    Vector::new(
+                        (x1 + x2) / 2.0,
+                        (y1 + y2) / 2.0,
+                        (z1 + z2) / 2.0,
+                        (w1 + w2) / 2.0,
+                    ),
+                    x2 - x1,
+                )));
+            }
+            CellContents::Walls(ref letters) => letters,
+        };
 
         let mut walls = Vec::new();
 
-        for c in s3.chars() {
+        for c in letters.chars() {
             let (middle, size) = match c {
                 'x' => (
                     Vector::new(x1, (y2 + y1) / 2.0, (z2 + z1) / 2.0, (w2 + w1) / 2.0),
@@ -130,30 +255,945 @@ impl Level {
                     Vector::new((x2 + x1) / 2.0, (y2 + y1) / 2.0, (z2 + z1) / 2.0, w2),
                     Vector::new(x2 - x1, y2 - y1, z2 - z1, 0.0),
                 ),
-                _ => return LineResult::Error,
+                _ => return Err(c),
             };
             let wall = Wall::new(middle, size);
             walls.push(wall);
         }
 
-        LineResult::Walls(walls)
+        Ok(LineResult::Walls(walls))
+    }
+}
+
+/// What can be wrong with a pose of the player in a level.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PoseError {
+    BadOrientation,
+    Outside,
+    Blocked,
+}
+
+impl Display for PoseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PoseError::BadOrientation => {
+                write!(
+                    f,
+                    "the right, up, front and ana directions aren't orthonormal or are mirrored"
+                )
+            }
+            PoseError::Outside => write!(f, "the position is outside of all the blocks"),
+            PoseError::Blocked => write!(f, "the position is inside a wall"),
+        }
+    }
+}
+
+/// Directives setting the player's start pose. They can appear between blocks of a level file,
+/// each followed by four coordinates.
+const DIRECTIVES: [&str; 5] = ["start", "right", "up", "front", "ana"];
+
+/// The lines of the start directives in the order of `DIRECTIVES`, for those given in the file.
+type DirectiveLines = [Option<usize>; 5];
+
+const ORTHONORMAL_EPSILON: f64 = 1e-6;
+
+/// Where the player starts and which way they face - the directions of the player's right, up,
+/// front and ana axes.
+#[derive(Clone, Copy)]
+pub struct StartPose {
+    pub position: Vector,
+    pub right: Vector,
+    pub up: Vector,
+    pub front: Vector,
+    pub ana: Vector,
+}
+
+impl Default for StartPose {
+    fn default() -> StartPose {
+        StartPose {
+            position: Vector::new(0.0, 0.0, 0.0, 0.0),
+            right: Vector::new(1.0, 0.0, 0.0, 0.0),
+            up: Vector::new(0.0, 1.0, 0.0, 0.0),
+            front: Vector::new(0.0, 0.0, 1.0, 0.0),
+            ana: Vector::new(0.0, 0.0, 0.0, 1.0),
+        }
+    }
+}
+
+impl StartPose {
+    fn directions(&self) -> [Vector; 4] {
+        [self.right, self.up, self.front, self.ana]
+    }
+
+    /// Whether the directions are orthonormal and not mirrored, so that the player's
+    /// orientation is a rotation of the default one.
+    fn is_proper_orientation(&self) -> bool {
+        let directions = self.directions();
+        let orthonormal = (0..4).all(|i| {
+            (0..4).all(|j| {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                (directions[i].dot(directions[j]) - expected).abs() < ORTHONORMAL_EPSILON
+            })
+        });
+        // the determinant of the matrix with the directions as columns
+        let determinant = self
+            .right
+            .dot(Vector::cross4(self.up, self.front, self.ana));
+        orthonormal && determinant > 0.0
+    }
+
+    /// The first two directions (0 for right, 1 for up, 2 for front and 3 for ana) that aren't
+    /// orthonormal - the same direction twice if it isn't a unit vector. `None` if all of them
+    /// are orthonormal.
+    fn clashing_directions(&self) -> Option<(usize, usize)> {
+        let directions = self.directions();
+        for j in 0..4 {
+            for i in 0..j + 1 {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                if (directions[i].dot(directions[j]) - expected).abs() >= ORTHONORMAL_EPSILON {
+                    return Some((i, j));
+                }
+            }
+        }
+        None
+    }
+
+    fn player(&self) -> Player {
+        Player::with_pose(self.position, self.right, self.up, self.front, self.ana)
+    }
+}
+
+const WALL_DIRECTIVE: &str = "wall";
+
+const ROTATION_PLANES: [&str; 6] = ["xy", "xz", "xw", "yz", "yw", "zw"];
+
+/// A wall declared on its own line of a level file:
+///
+/// ```text
+/// wall CX CY CZ CW EX EY EZ [PLANE DEGREES]...
+/// ```
+///
+/// The wall is centered at (CX, CY, CZ, CW) and spans EX, EY and EZ along its own x, y and z
+/// axes, with its w axis perpendicular to it. Every `PLANE DEGREES` pair (PLANE being one of
+/// xy, xz, xw, yz, yw and zw) rotates it around the center, in the order given.
+#[derive(Clone)]
+pub struct OrientedWall {
+    center: Vector,
+    extents: [f64; 3],
+    rotations: Vec<(String, f64)>,
+}
+
+impl OrientedWall {
+    fn parse(line: usize, text: &str) -> Result<OrientedWall, LevelError> {
+        let bad_wall = || LevelError::BadWall {
+            line: line,
+            text: text.trim().to_owned(),
+        };
+        let words: Vec<&str> = text.split_whitespace().skip(1).collect();
+        if words.len() < 7 || (words.len() - 7) % 2 != 0 {
+            return Err(bad_wall());
+        }
+        let mut numbers = [0.0; 7];
+        for (number, word) in numbers.iter_mut().zip(words.iter()) {
+            *number = word.parse().map_err(|_| bad_wall())?;
+        }
+        let mut rotations = Vec::new();
+        for pair in words[7..].chunks(2) {
+            let angle = pair[1].parse().map_err(|_| bad_wall())?;
+            if !ROTATION_PLANES.contains(&pair[0]) {
+                return Err(bad_wall());
+            }
+            rotations.push((pair[0].to_owned(), angle));
+        }
+        Ok(OrientedWall {
+            center: Vector::new(numbers[0], numbers[1], numbers[2], numbers[3]),
+            extents: [numbers[4], numbers[5], numbers[6]],
+            rotations: rotations,
+        })
+    }
+
+    fn orientation(&self) -> Matrix {
+        self.rotations
+            .iter()
+            .fold(Matrix::identity(), |orientation, &(ref plane, degrees)| {
+                let phi = degrees.to_radians();
+                let rotation = match &plane[..] {
+                    "xy" => Matrix::rotation_xy(phi),
+                    "xz" => Matrix::rotation_xz(phi),
+                    "xw" => Matrix::rotation_xw(phi),
+                    "yz" => Matrix::rotation_yz(phi),
+                    "yw" => Matrix::rotation_yw(phi),
+                    "zw" => Matrix::rotation_zw(phi),
+                    _ => unreachable!(),
+                };
+                rotation * orientation
+            })
+    }
+
+    fn wall(&self) -> Wall {
+        Wall::oriented(self.center, self.orientation(), self.extents)
+    }
+
+    fn format(&self) -> String {
+        let mut text = format!(
+            "{} {} {} {} {}",
+            WALL_DIRECTIVE,
+            Level::format_coords(self.center),
+            self.extents[0],
+            self.extents[1],
+            self.extents[2]
+        );
+        for &(ref plane, degrees) in &self.rotations {
+            text.push_str(&format!(" {} {}", plane, degrees));
+        }
+        text
+    }
+}
+
+/// How many times a single move can be deflected by walls - enough for a corner formed by a
+/// wall along each axis.
+const MAX_SLIDES: usize = 5;
+
+/// How far the player is kept from the walls they hit.
+const SKIN: f64 = 1e-6;
+
+const MIN_MOTION: f64 = 1e-9;
+
+/// Where a move of the player ended.
+pub struct Movement {
+    pub position: Vector,
+    pub reached_target: bool,
+}
+
+pub struct Level {
+    cells: Vec<Cell>,
+    index: SpatialIndex,
+    oriented_walls: Vec<OrientedWall>,
+    start: Option<StartPose>,
+    walls: Vec<Wall>,
+    target: Target,
+    player: Player,
+}
+
+impl Level {
+    pub fn from_file<P: AsRef<Path>>(file_name: P) -> Result<Level, LevelError> {
+        let f = File::open(file_name)?;
+        Level::from_reader(BufReader::new(f))
+    }
+
+    pub fn from_reader<R: BufRead>(reader: R) -> Result<Level, LevelError> {
+        let mut state = ReadState::NoLines;
+        let mut cells = Vec::new();
+        let mut lines = Vec::new();
+        let mut directives = Vec::new();
+        let mut oriented_walls = Vec::new();
+
+        for (index, line) in reader.lines().enumerate() {
+            let line_num = index + 1;
+            let line = line?;
+            match state {
+                // blank lines between blocks are allowed
+                ReadState::NoLines if line.trim().is_empty() => (),
+                ReadState::NoLines if line.split_whitespace().next() == Some(WALL_DIRECTIVE) => {
+                    oriented_walls.push(OrientedWall::parse(line_num, &line)?)
+                }
+                ReadState::NoLines if Level::is_directive(&line) => {
+                    directives.push((line_num, line))
+                }
+                ReadState::NoLines => state = ReadState::OneLine(line_num, line),
+                ReadState::OneLine(n, s) => state = ReadState::TwoLines(n, s, line),
+                ReadState::TwoLines(n, s1, s2) => {
+                    cells.push(Level::process_lines(n, &s1, &s2, &line)?);
+                    lines.push(n);
+                    state = ReadState::NoLines;
+                }
+            }
+        }
+
+        match state {
+            ReadState::NoLines => (),
+            ReadState::OneLine(n, s) | ReadState::TwoLines(n, s, _) => {
+                return Err(LevelError::PartialBlock { line: n, text: s })
+            }
+        }
+
+        let start = Level::parse_start(&directives)?;
+        Level::build(cells, &lines, oriented_walls, start)
+    }
+
+    fn is_directive(line: &str) -> bool {
+        let keyword = line.split_whitespace().next().unwrap_or("");
+        DIRECTIVES.contains(&keyword)
+    }
+
+    /// Reads the start pose from the directive lines. Returns it with the line of every
+    /// directive, or `None` if there were no directives.
+    fn parse_start(
+        directives: &[(usize, String)],
+    ) -> Result<Option<(DirectiveLines, StartPose)>, LevelError> {
+        let coord_line = Level::coord_regex();
+        let mut start = StartPose::default();
+        let mut lines: DirectiveLines = [None; 5];
+        for &(line, ref text) in directives {
+            let text = text.trim();
+            let keyword = text.split_whitespace().next().unwrap();
+            let directive = DIRECTIVES.iter().position(|&d| d == keyword).unwrap();
+            if lines[directive].is_some() {
+                return Err(LevelError::DuplicateDirective {
+                    line: line,
+                    text: text.to_owned(),
+                });
+            }
+            lines[directive] = Some(line);
+            let coords = text[keyword.len()..].trim_start();
+            let vector = Level::parse_coords(&coord_line, line, coords).map_err(|_| {
+                LevelError::BadCoordinate {
+                    line: line,
+                    text: text.to_owned(),
+                }
+            })?;
+            match keyword {
+                "start" => start.position = vector,
+                "right" => start.right = vector,
+                "up" => start.up = vector,
+                "front" => start.front = vector,
+                "ana" => start.ana = vector,
+                _ => unreachable!(),
+            }
+        }
+        if directives.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some((lines, start)))
+        }
+    }
+
+    /// Creates a level out of cells that didn't come from a file. Line numbers in errors refer
+    /// to the file that `write_to` would produce.
+    pub fn from_cells(cells: Vec<Cell>) -> Result<Level, LevelError> {
+        let lines: Vec<_> = (0..cells.len()).map(|i| 3 * i + 1).collect();
+        Level::build(cells, &lines, Vec::new(), None)
+    }
+
+    /// Creates the game objects for the cells; `lines` holds the line on which each cell starts.
+    /// The start pose, if there is one, comes with the lines of its directives.
+    fn build(
+        cells: Vec<Cell>,
+        lines: &[usize],
+        oriented_walls: Vec<OrientedWall>,
+        start: Option<(DirectiveLines, StartPose)>,
+    ) -> Result<Level, LevelError> {
+        let mut target = None;
+        let mut walls = Vec::new();
+
+        for (cell, &line) in cells.iter().zip(lines) {
+            match cell.objects() {
+                Ok(LineResult::Target(t)) => {
+                    if target.is_some() {
+                        return Err(LevelError::DuplicateTarget {
+                            line: line,
+                            text: Level::format_coords(cell.corner1),
+                        });
+                    }
+                    target = Some(t);
+                }
+                Ok(LineResult::Walls(mut w)) => walls.append(&mut w),
+                Err(c) => {
+                    let text = match cell.contents {
+                        CellContents::Walls(ref letters) => letters.clone(),
+                        CellContents::Target => unreachable!(),
+                    };
+                    return Err(LevelError::UnknownWall {
+                        line: line + 2,
+                        text: text,
+                        wall: c,
+                    });
+                }
+            }
+        }
+
+        let target = target.ok_or(LevelError::MissingTarget)?;
+        walls.extend(oriented_walls.iter().map(OrientedWall::wall));
+        if let Some((ref lines, ref pose)) = start {
+            Level::check_start(pose, lines, &cells, &walls)?;
+        }
+        let bounds: Vec<_> = walls.iter().map(|wall| wall.bounds()).collect();
+
+        Ok(Level {
+            player: start
+                .map(|(_, pose)| pose.player())
+                .unwrap_or_else(Player::new),
+            target: target,
+            index: SpatialIndex::new(&bounds),
+            walls: walls,
+            oriented_walls: oriented_walls,
+            start: start.map(|(_, pose)| pose),
+            cells: cells,
+        })
     }
 
-    pub fn game_objects<'a>(&'a mut self) -> impl Iterator<Item = &'a mut GameObject> {
-        once(&mut self.player as &mut GameObject)
-            .chain(once(&mut self.target as &mut GameObject))
-            .chain(self.walls.iter_mut().map(|x| x as &mut GameObject))
+    /// Checks that the start pose is a proper orientation, and that the player starts inside a
+    /// block without touching any walls. Errors point at the directive at fault: the `start`
+    /// line for the position and the line of a direction for the orientation. When that one
+    /// wasn't given, they point at the first directive.
+    fn check_start(
+        pose: &StartPose,
+        lines: &DirectiveLines,
+        cells: &[Cell],
+        walls: &[Wall],
+    ) -> Result<(), LevelError> {
+        let first = lines.iter().filter_map(|&line| line).min().unwrap_or(0);
+        let line_of = |directive: usize| lines[directive].unwrap_or(first);
+        Level::check_pose_in(pose, cells, walls).map_err(|err| match err {
+            PoseError::BadOrientation => {
+                // the direction given last out of those that clash, or out of all of them if
+                // the frame is only mirrored
+                let suspects = match pose.clashing_directions() {
+                    Some((i, j)) => vec![i, j],
+                    None => (0..4).collect(),
+                };
+                let line = suspects
+                    .into_iter()
+                    .filter_map(|direction| lines[1 + direction])
+                    .max()
+                    .unwrap_or(first);
+                LevelError::BadOrientation { line: line }
+            }
+            PoseError::Outside => LevelError::StartOutside { line: line_of(0) },
+            PoseError::Blocked => LevelError::StartBlocked { line: line_of(0) },
+        })
     }
 
-    pub fn collidables<'a>(&'a self) -> impl Iterator<Item = &'a Collidable> {
-        once(&self.target as &Collidable).chain(self.walls.iter().map(|x| x as &Collidable))
+    fn check_pose_in(pose: &StartPose, cells: &[Cell], walls: &[Wall]) -> Result<(), PoseError> {
+        if !pose.is_proper_orientation() {
+            return Err(PoseError::BadOrientation);
+        }
+        let p = pose.position;
+        let inside = cells.iter().any(|cell| {
+            let (min, max) = (cell.min(), cell.max());
+            (0..4).all(|i| p.coord(i) >= min.coord(i) && p.coord(i) <= max.coord(i))
+        });
+        if !inside {
+            return Err(PoseError::Outside);
+        }
+        let action = AdditionalAction::MoveTo(p);
+        if walls.iter().any(|wall| wall.collides(&action)) {
+            return Err(PoseError::Blocked);
+        }
+        Ok(())
     }
 
-    pub fn wins(&self, action: &AdditionalAction) -> bool {
-        self.target.collides(action)
+    /// Checks a pose the same way as the start pose of a level file.
+    pub fn check_pose(&self, pose: &StartPose) -> Result<(), PoseError> {
+        Level::check_pose_in(pose, &self.cells, &self.walls)
+    }
+
+    /// A hash of everything `write_to` writes, so the same for the same level however it was
+    /// created. It's stable between runs and builds of the game.
+    pub fn fingerprint(&self) -> u64 {
+        let mut text = Vec::new();
+        self.write_to(&mut text)
+            .expect("writing to a Vec can't fail");
+        // 64-bit FNV-1a
+        text.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+        })
+    }
+
+    fn parse_coords(coord_line: &Regex, line: usize, text: &str) -> Result<Vector, LevelError> {
+        let bad_coordinate = || LevelError::BadCoordinate {
+            line: line,
+            text: text.to_owned(),
+        };
+        let cap = coord_line.captures(text).ok_or_else(bad_coordinate)?;
+        let mut coords = [0.0; 4];
+        for (i, name) in ["x", "y", "z", "w"].iter().enumerate() {
+            coords[i] = cap
+                .name(name)
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(bad_coordinate)?;
+        }
+        Ok(Vector::new(coords[0], coords[1], coords[2], coords[3]))
+    }
+
+    fn format_coords(v: Vector) -> String {
+        format!("{} {} {} {}", v.x(), v.y(), v.z(), v.w())
+    }
+
+    fn coord_regex() -> Regex {
+        Regex::new(r"^(?P<x>-?\d+(\.\d+)?)\s+(?P<y>-?\d+(\.\d+)?)\s+(?P<z>-?\d+(\.\d+)?)\s+(?P<w>-?\d+(\.\d+)?)\s*$").unwrap()
+    }
+
+    fn process_lines(line: usize, s1: &str, s2: &str, s3: &str) -> Result<Cell, LevelError> {
+        let coord_line = Level::coord_regex();
+        let corner1 = Level::parse_coords(&coord_line, line, s1)?;
+        let corner2 = Level::parse_coords(&coord_line, line + 1, s2)?;
+        let contents = match s3.trim() {
+            // a blank line here means the wall line is missing, not that there are no walls
+            "" => return Err(LevelError::MissingWalls { line: line + 2 }),
+            "T" => CellContents::Target,
+            NO_WALLS => CellContents::Walls(String::new()),
+            letters => CellContents::Walls(letters.to_owned()),
+        };
+        Ok(Cell::new(corner1, corner2, contents))
+    }
+
+    /// Writes the level in the same format `from_reader` reads.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        if let Some(ref start) = self.start {
+            let vectors = [
+                start.position,
+                start.right,
+                start.up,
+                start.front,
+                start.ana,
+            ];
+            for (keyword, &v) in DIRECTIVES.iter().zip(vectors.iter()) {
+                writeln!(writer, "{} {}", keyword, Level::format_coords(v))?;
+            }
+        }
+        for wall in &self.oriented_walls {
+            writeln!(writer, "{}", wall.format())?;
+        }
+        for cell in &self.cells {
+            writeln!(writer, "{}", Level::format_coords(cell.corner1))?;
+            writeln!(writer, "{}", Level::format_coords(cell.corner2))?;
+            match cell.contents {
+                CellContents::Walls(ref letters) if letters.is_empty() => {
+                    writeln!(writer, "{}", NO_WALLS)?
+                }
+                CellContents::Walls(ref letters) => writeln!(writer, "{}", letters)?,
+                CellContents::Target => writeln!(writer, "T")?,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn cells(&self) -> &[Cell] {
+        &self.cells
+    }
+
+    pub fn walls(&self) -> &[Wall] {
+        &self.walls
+    }
+
+    pub fn target(&self) -> &Target {
+        &self.target
+    }
+
+    /// The objects that can show up in the slice of space at `hyperplane` - the player, the
+    /// target and the walls the hyperplane passes near.
+    pub fn visible_objects<'a>(
+        &'a self,
+        hyperplane: Hyperplane,
+    ) -> impl Iterator<Item = &'a GameObject> {
+        let walls = self.index.query_hyperplane(hyperplane);
+        once(&self.player as &GameObject)
+            .chain(once(&self.target as &GameObject))
+            .chain(
+                walls
+                    .into_iter()
+                    .map(move |i| &self.walls[i] as &GameObject),
+            )
+    }
+
+    /// Carries out the player's action, letting them move only as far as the walls allow.
+    /// Returns whether the player reached the target.
+    pub fn move_player(&mut self, action: AdditionalAction) -> bool {
+        match action {
+            AdditionalAction::MoveTo(destination) => {
+                let start = self.player.position();
+                let movement = self.resolve_move(start, destination - start);
+                self.player
+                    .perform_action(AdditionalAction::MoveTo(movement.position));
+                movement.reached_target
+            }
+            AdditionalAction::None => false,
+        }
+    }
+
+    /// Moves the player from `start` by `motion` as far as the walls let them. When a wall is
+    /// hit, the rest of the motion continues along its surface, so the player slides instead
+    /// of stopping dead.
+    pub fn resolve_move(&self, start: Vector, motion: Vector) -> Movement {
+        let mut position = start;
+        let mut remaining = motion;
+
+        for _ in 0..MAX_SLIDES {
+            if remaining.len() < MIN_MOTION {
+                break;
+            }
+            let reach = Aabb::around(position, position + remaining);
+            let contact = self
+                .index
+                .query_box(&reach)
+                .into_iter()
+                .filter_map(|i| self.walls[i].sweep(position, remaining))
+                .min_by(|a, b| a.time.partial_cmp(&b.time).unwrap_or(Ordering::Equal));
+
+            let time = contact.map_or(1.0, |contact| contact.time);
+            if let Some(target) = self.target.sweep(position, remaining) {
+                if target.time <= time {
+                    return Movement {
+                        position: position + remaining * target.time,
+                        reached_target: true,
+                    };
+                }
+            }
+
+            match contact {
+                None => {
+                    position = position + remaining;
+                    break;
+                }
+                Some(contact) => {
+                    // stop at the wall, a hair away from it so that the next sweep doesn't
+                    // start inside
+                    position = position + remaining * contact.time + contact.normal * SKIN;
+                    remaining = remaining * (1.0 - contact.time);
+                    let into_wall = remaining.dot(contact.normal);
+                    if into_wall < 0.0 {
+                        remaining = remaining - contact.normal * into_wall;
+                    }
+                }
+            }
+        }
+
+        Movement {
+            position: position,
+            reached_target: self.target.collides(&AdditionalAction::MoveTo(position)),
+        }
     }
 
     pub fn player(&mut self) -> &mut Player {
         &mut self.player
     }
 }
+
+impl FromStr for Level {
+    type Err = LevelError;
+
+    fn from_str(s: &str) -> Result<Level, LevelError> {
+        Level::from_reader(s.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::input::{Action, ActionState};
+    use super::super::objects::{AdditionalAction, Collidable};
+    use super::{Level, LevelError};
+    use graph4d::geometry::Vector;
+    use std::str::FromStr;
+
+    const SIMPLE_LEVEL: &'static str = "-2.5 -2.5 -2.5 -2.5
+2.5 2.5 2.5 2.5
+xXyYzwW
+-2.5 -2.5 2.5 -2.5
+2.5 2.5 7.5 2.5
+T
+";
+
+    #[test]
+    fn test_from_str() {
+        let level = Level::from_str(SIMPLE_LEVEL).unwrap();
+        assert_eq!(level.walls.len(), 7);
+    }
+
+    #[test]
+    fn test_shipped_levels() {
+        let level1: Level = include_str!("../level1.dat").parse().unwrap();
+        let level2: Level = include_str!("../level2.dat").parse().unwrap();
+        assert!(level1.walls.len() > 0);
+        assert!(level2.walls.len() > 0);
+    }
+
+    fn round_trip(original: &str) {
+        let level = Level::from_str(original).unwrap();
+        let mut written = Vec::new();
+        level.write_to(&mut written).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        let reread = Level::from_str(&written).unwrap();
+        assert!(level.cells == reread.cells);
+        assert_eq!(level.walls.len(), reread.walls.len());
+        // the shipped files use CRLF and have no newline at the end
+        assert_eq!(written, format!("{}\n", original.replace("\r\n", "\n")));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        round_trip(include_str!("../level1.dat"));
+        round_trip(include_str!("../level2.dat"));
+    }
+
+    #[test]
+    fn test_bad_coordinate() {
+        let text = SIMPLE_LEVEL.replace("2.5 2.5 7.5 2.5", "2.5 2.5 seven 2.5");
+        match Level::from_str(&text) {
+            Err(LevelError::BadCoordinate { line, text }) => {
+                assert_eq!(line, 5);
+                assert_eq!(text, "2.5 2.5 seven 2.5");
+            }
+            _ => panic!("expected a bad coordinate error"),
+        }
+    }
+
+    #[test]
+    fn test_trailing_garbage() {
+        let text = SIMPLE_LEVEL.replace("2.5 2.5 7.5 2.5", "2.5 2.5 7.5 2.5 9");
+        match Level::from_str(&text) {
+            Err(LevelError::BadCoordinate { line, .. }) => assert_eq!(line, 5),
+            _ => panic!("expected a bad coordinate error"),
+        }
+        let text = SIMPLE_LEVEL.replace("2.5 2.5 7.5 2.5", "2.5 2.5 7.5 2.5x");
+        match Level::from_str(&text) {
+            Err(LevelError::BadCoordinate { line, .. }) => assert_eq!(line, 5),
+            _ => panic!("expected a bad coordinate error"),
+        }
+    }
+
+    #[test]
+    fn test_empty_wall_line() {
+        let text = SIMPLE_LEVEL.replace("xXyYzwW", "");
+        match Level::from_str(&text) {
+            Err(LevelError::MissingWalls { line }) => assert_eq!(line, 3),
+            _ => panic!("expected a missing walls error"),
+        }
+
+        // a block without walls is written with a dash instead
+        let text = SIMPLE_LEVEL.replace("xXyYzwW", "-");
+        let level = Level::from_str(&text).unwrap();
+        assert_eq!(level.walls.len(), 0);
+        let mut written = Vec::new();
+        level.write_to(&mut written).unwrap();
+        assert_eq!(String::from_utf8(written).unwrap(), text);
+    }
+
+    #[test]
+    fn test_unknown_wall() {
+        let text = SIMPLE_LEVEL.replace("xXyYzwW", "xXyYzqW");
+        match Level::from_str(&text) {
+            Err(LevelError::UnknownWall { line, wall, .. }) => {
+                assert_eq!(line, 3);
+                assert_eq!(wall, 'q');
+            }
+            _ => panic!("expected an unknown wall error"),
+        }
+    }
+
+    #[test]
+    fn test_missing_target() {
+        let text = SIMPLE_LEVEL.replace("T", "Z");
+        match Level::from_str(&text) {
+            Err(LevelError::MissingTarget) => (),
+            _ => panic!("expected a missing target error"),
+        }
+    }
+
+    #[test]
+    fn test_duplicate_target() {
+        let text = format!(
+            "{}{}",
+            SIMPLE_LEVEL, "-2.5 -2.5 7.5 -2.5\n2.5 2.5 12.5 2.5\nT\n"
+        );
+        match Level::from_str(&text) {
+            Err(LevelError::DuplicateTarget { line, .. }) => assert_eq!(line, 7),
+            _ => panic!("expected a duplicate target error"),
+        }
+    }
+
+    #[test]
+    fn test_partial_block() {
+        let text = format!(
+            "{}{}",
+            SIMPLE_LEVEL, "-2.5 -2.5 7.5 -2.5\n2.5 2.5 12.5 2.5\n"
+        );
+        match Level::from_str(&text) {
+            Err(LevelError::PartialBlock { line, .. }) => assert_eq!(line, 7),
+            _ => panic!("expected a partial block error"),
+        }
+    }
+
+    const START_POSE: &'static str = "start 0 0 1 0
+right 0 0 1 0
+up 0 1 0 0
+front -1 0 0 0
+ana 0 0 0 1
+";
+
+    #[test]
+    fn test_start_pose() {
+        let text = format!("{}{}", START_POSE, SIMPLE_LEVEL);
+        let mut level = Level::from_str(&text).unwrap();
+        assert!(level.player().position() == Vector::new(0.0, 0.0, 1.0, 0.0));
+        // the player faces along -x
+        level.player().go(Vector::new(0.0, 0.0, 1.0, 0.0));
+        assert!(level.player().position() == Vector::new(-1.0, 0.0, 1.0, 0.0));
+
+        let mut written = Vec::new();
+        level.write_to(&mut written).unwrap();
+        assert_eq!(String::from_utf8(written).unwrap(), text);
+    }
+
+    #[test]
+    fn test_partial_start_pose() {
+        // directives can come between blocks, and missing directions keep their defaults
+        let text = SIMPLE_LEVEL.replace("xXyYzwW\n", "xXyYzwW\n\nstart 0 0 5 0\n\n");
+        let mut level = Level::from_str(&text).unwrap();
+        assert!(level.player().position() == Vector::new(0.0, 0.0, 5.0, 0.0));
+    }
+
+    #[test]
+    fn test_bad_start_pose() {
+        let text = format!("start 0 0 1 0\nright 1 0 1 0\n{}", SIMPLE_LEVEL);
+        match Level::from_str(&text) {
+            Err(LevelError::BadOrientation { line }) => assert_eq!(line, 2),
+            _ => panic!("expected a bad orientation error"),
+        }
+
+        // orthonormal, but a mirror image
+        let text = format!("start 0 0 1 0\nana 0 0 0 -1\n{}", SIMPLE_LEVEL);
+        match Level::from_str(&text) {
+            Err(LevelError::BadOrientation { line }) => assert_eq!(line, 2),
+            _ => panic!("expected a bad orientation error"),
+        }
+
+        let text = format!("up 0 1 0 0\nstart 0 0 20 0\n{}", SIMPLE_LEVEL);
+        match Level::from_str(&text) {
+            Err(LevelError::StartOutside { line }) => assert_eq!(line, 2),
+            _ => panic!("expected a start outside error"),
+        }
+
+        // a direction clashing with a default one is blamed, not the default, and of two given
+        // directions the later one
+        let text = format!(
+            "start 0 0 1 0\nright 0 1 0 0\nfront 0 0 1 0\n{}",
+            SIMPLE_LEVEL
+        );
+        match Level::from_str(&text) {
+            Err(LevelError::BadOrientation { line }) => assert_eq!(line, 2),
+            _ => panic!("expected a bad orientation error"),
+        }
+        let text = format!("ana 0 0 0 1\nright 0 0 0 1\n{}", SIMPLE_LEVEL);
+        match Level::from_str(&text) {
+            Err(LevelError::BadOrientation { line }) => assert_eq!(line, 2),
+            _ => panic!("expected a bad orientation error"),
+        }
+
+        let text = format!("start 2.4 0 0 0\n{}", SIMPLE_LEVEL);
+        match Level::from_str(&text) {
+            Err(LevelError::StartBlocked { line }) => assert_eq!(line, 1),
+            _ => panic!("expected a start blocked error"),
+        }
+
+        let text = format!("start 0 0 0 0\nstart 0 0 1 0\n{}", SIMPLE_LEVEL);
+        match Level::from_str(&text) {
+            Err(LevelError::DuplicateDirective { line, .. }) => assert_eq!(line, 2),
+            _ => panic!("expected a duplicate directive error"),
+        }
+
+        let text = format!("start 0 0 one 0\n{}", SIMPLE_LEVEL);
+        match Level::from_str(&text) {
+            Err(LevelError::BadCoordinate { line, .. }) => assert_eq!(line, 1),
+            _ => panic!("expected a bad coordinate error"),
+        }
+    }
+
+    #[test]
+    fn test_oriented_wall() {
+        // a ramp across the first block, tilted in the z-w plane
+        let text = format!("wall 0 0 0 0 5 5 5 zw 45\n{}", SIMPLE_LEVEL);
+        let level = Level::from_str(&text).unwrap();
+        assert_eq!(level.walls.len(), 8);
+        let on_ramp = AdditionalAction::MoveTo(Vector::new(0.0, 0.0, 1.0, 1.0));
+        let off_ramp = AdditionalAction::MoveTo(Vector::new(0.0, 0.0, 1.0, -1.0));
+        assert!(level.walls[7].collides(&on_ramp));
+        assert!(!level.walls[7].collides(&off_ramp));
+
+        let mut written = Vec::new();
+        level.write_to(&mut written).unwrap();
+        assert_eq!(String::from_utf8(written).unwrap(), text);
+
+        // the start has to avoid oriented walls too
+        let text = format!("start 0 0 1 1\n{}", text);
+        match Level::from_str(&text) {
+            Err(LevelError::StartBlocked { line }) => assert_eq!(line, 1),
+            _ => panic!("expected a start blocked error"),
+        }
+    }
+
+    #[test]
+    fn test_bad_wall() {
+        for line in &[
+            "wall 0 0 0 0 5 5",
+            "wall 0 0 0 0 5 5 5 xq 45",
+            "wall 0 0 0 0 5 5 5 xw",
+        ] {
+            let text = format!("{}{}\n", SIMPLE_LEVEL, line);
+            match Level::from_str(&text) {
+                Err(LevelError::BadWall { line, .. }) => assert_eq!(line, 7),
+                _ => panic!("expected a bad wall error"),
+            }
+        }
+    }
+
+    fn resolve(level: &Level, motion: Vector) -> Vector {
+        let movement = level.resolve_move(Vector::new(0.0, 0.0, 0.0, 0.0), motion);
+        assert!(!movement.reached_target);
+        movement.position
+    }
+
+    #[test]
+    fn test_no_tunnelling() {
+        let level = Level::from_str(SIMPLE_LEVEL).unwrap();
+        // a huge frame time turns into a huge step, which must still stop at the wall
+        for &step in &[10.0, 1000.0, 1e6] {
+            let position = resolve(&level, Vector::new(step, 0.0, 0.0, 0.0));
+            assert!(position.x() <= 2.3 && position.x() > 2.3 - 1e-3);
+            let position = resolve(&level, Vector::new(0.0, 0.0, 0.0, -step));
+            assert!(position.w() >= -2.3 && position.w() < -2.3 + 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_no_tunnelling_through_thin_wall() {
+        // a wall with no thickness across the first block, between the player and the target
+        let text = format!("wall 0 0 1 0 5 5 5 zw 90\n{}", SIMPLE_LEVEL);
+        let mut actions = ActionState::new();
+        actions.set(Action::MoveForward, true);
+        // a long frame moves the player well past the wall in one step
+        for &frame_time in &[1.0, 10.0] {
+            let mut level = Level::from_str(&text).unwrap();
+            let action = level.player().handle_input(&actions, frame_time);
+            assert!(!level.move_player(action));
+            let z = level.player().position().z();
+            assert!(z < 0.8 && z > 0.8 - 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_sliding() {
+        let level = Level::from_str(SIMPLE_LEVEL).unwrap();
+        // moving diagonally into the x wall keeps the y part of the motion
+        let position = resolve(&level, Vector::new(5.0, 1.0, 0.0, 0.0));
+        assert!((position.x() - 2.3).abs() < 1e-3);
+        assert!((position.y() - 1.0).abs() < 1e-9);
+
+        // into a corner, the player stops in it
+        let position = resolve(&level, Vector::new(5.0, 5.0, 0.0, 0.0));
+        assert!((position.x() - 2.3).abs() < 1e-3);
+        assert!((position.y() - 2.3).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_reaching_target() {
+        let level = Level::from_str(SIMPLE_LEVEL).unwrap();
+        let movement = level.resolve_move(
+            Vector::new(0.0, 0.0, 0.0, 0.0),
+            Vector::new(0.0, 0.0, 1000.0, 0.0),
+        );
+        assert!(movement.reached_target);
+        // the target is 5 wide around z = 5; the player touches it at 2.5 - 0.2
+        assert!((movement.position.z() - 2.3).abs() < 1e-9);
+    }
+}
@@ -1,93 +1,445 @@
+mod bot;
+mod game;
+mod generator;
+mod input;
+mod joystick;
 mod levels;
 mod objects;
+mod pack;
+mod pathfinder;
+mod replay;
+mod save;
+mod simulation;
+mod spatial;
 
+use bot::Bot;
+use game::{Game, Stage};
+use generator::Maze;
+use input::{AnalogState, Bindings, KeyboardState, MouseState};
+use joystick::Joystick;
 use levels::Level;
+use pack::Pack;
+use replay::{Replay, ReplayOrigin};
+use save::SavedGame;
 
-use glium::glutin::{ElementState, VirtualKeyCode};
+use glium::glutin::{CursorState, ElementState, VirtualKeyCode};
 use glium::{DisplayBuild, Surface};
-use std::collections::HashSet;
-use std::time::SystemTime;
+use graph4d::camera::Camera;
+use graph4d::projection::Projection;
+use graph4d::renderer::Renderer;
+use std::env;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-pub struct KeyboardState {
-    pressed_keys: HashSet<VirtualKeyCode>,
+const ZOOM_STEP: f64 = 1.25;
+
+const USAGE: &'static str =
+    "usage: 4d-labyrinth [--bindings FILE] [--joystick DEVICE] [--mouse-look] [--autoplay] [--record FILE] [--replay FILE] [--continue FILE] [PACK_OR_LEVEL_FILE | --generate SIZE [--seed SEED] [--save FILE]]";
+
+struct Options {
+    path: Option<String>,
+    generate: Option<usize>,
+    seed: Option<u64>,
+    save: Option<String>,
+    bindings: Option<String>,
+    joystick: Option<String>,
+    mouse_look: bool,
+    autoplay: bool,
+    record: Option<String>,
+    replay: Option<String>,
+    resume: Option<String>,
 }
 
-impl KeyboardState {
-    pub fn new() -> KeyboardState {
-        KeyboardState {
-            pressed_keys: HashSet::new(),
+impl Options {
+    fn from_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+        let mut options = Options {
+            path: None,
+            generate: None,
+            seed: None,
+            save: None,
+            bindings: None,
+            joystick: None,
+            mouse_look: false,
+            autoplay: false,
+            record: None,
+            replay: None,
+            resume: None,
+        };
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+            match &arg[..] {
+                "--generate" => {
+                    let size = value()?;
+                    match size.parse() {
+                        Ok(size) if size >= 2 => options.generate = Some(size),
+                        _ => return Err(format!("invalid maze size: {}", size)),
+                    }
+                }
+                "--seed" => {
+                    let seed = value()?;
+                    options.seed = Some(
+                        seed.parse()
+                            .map_err(|_| format!("invalid seed: {}", seed))?,
+                    );
+                }
+                "--save" => options.save = Some(value()?),
+                "--bindings" => options.bindings = Some(value()?),
+                "--joystick" => options.joystick = Some(value()?),
+                "--mouse-look" => options.mouse_look = true,
+                "--autoplay" => options.autoplay = true,
+                "--record" => options.record = Some(value()?),
+                "--replay" => options.replay = Some(value()?),
+                "--continue" => options.resume = Some(value()?),
+                _ if !arg.starts_with("--") && options.path.is_none() => options.path = Some(arg),
+                _ => return Err(format!("unknown argument: {}", arg)),
+            }
+        }
+        if options.generate.is_none() && (options.seed.is_some() || options.save.is_some()) {
+            return Err("--seed and --save only make sense with --generate".to_owned());
+        }
+        if options.generate.is_some() && options.path.is_some() {
+            return Err("--generate can't be used together with a level file".to_owned());
+        }
+        if options.autoplay && options.replay.is_some() {
+            return Err("--autoplay can't be used together with --replay".to_owned());
+        }
+        if options.resume.is_some() && (options.record.is_some() || options.replay.is_some()) {
+            return Err("--continue can't be used together with --record or --replay".to_owned());
         }
+        Ok(options)
     }
+}
 
-    fn pressed(&mut self, key: VirtualKeyCode) {
-        self.pressed_keys.insert(key);
+fn generate_level(size: usize, seed: u64, save: Option<&str>) -> Result<Level, String> {
+    println!("Generating a maze of size {} from seed {}", size, seed);
+    let level =
+        Level::from_cells(Maze::generate(size, seed).cells()).map_err(|err| err.to_string())?;
+    if let Some(file_name) = save {
+        let file = File::create(file_name).map_err(|err| format!("{}: {}", file_name, err))?;
+        level
+            .write_to(file)
+            .map_err(|err| format!("{}: {}", file_name, err))?;
     }
+    Ok(level)
+}
+
+/// The levels to play, with the size and the seed of the maze if it was generated.
+type Stages = (Vec<Stage>, Option<(usize, u64)>);
 
-    fn released(&mut self, key: VirtualKeyCode) {
-        self.pressed_keys.remove(&key);
+fn load_stages(options: &Options) -> Result<Stages, String> {
+    if let Some(size) = options.generate {
+        let seed = options.seed.unwrap_or_else(|| {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+            now.as_secs() ^ now.subsec_nanos() as u64
+        });
+        let save = options.save.as_ref().map(|s| &s[..]);
+        let stage = Stage {
+            title: format!("Random maze {0}x{0}x{0}x{0}", size),
+            par: None,
+            fov: None,
+            level: generate_level(size, seed, save)?,
+        };
+        return Ok((vec![stage], Some((size, seed))));
     }
+    let pack = match options.path {
+        Some(ref path) if path.ends_with(".dat") => Pack::single(path),
+        Some(ref path) => Pack::from_file(path).map_err(|err| format!("{}: {}", path, err))?,
+        None => Pack::builtin(),
+    };
+    let stages = pack
+        .entries()
+        .iter()
+        .map(Stage::load)
+        .collect::<Result<_, _>>()?;
+    Ok((stages, None))
+}
 
-    pub fn is_pressed(&self, key: VirtualKeyCode) -> bool {
-        self.pressed_keys.contains(&key)
+/// Handles the zoom keys: +/- change the field of view, PageUp/PageDown move the camera closer
+/// to or further from the player.
+fn zoom(key: VirtualKeyCode, renderer: &mut Renderer, level: &mut Level) {
+    let mut projection = renderer.projection();
+    match key {
+        VirtualKeyCode::Equals | VirtualKeyCode::Add => projection.zoom(ZOOM_STEP as f32),
+        VirtualKeyCode::Minus | VirtualKeyCode::Subtract => projection.zoom(1.0 / ZOOM_STEP as f32),
+        VirtualKeyCode::PageUp => {
+            let offset = level.player().camera_offset();
+            level.player().set_camera_offset(offset / ZOOM_STEP);
+        }
+        VirtualKeyCode::PageDown => {
+            let offset = level.player().camera_offset();
+            level.player().set_camera_offset(offset * ZOOM_STEP);
+        }
+        _ => (),
+    }
+    renderer.set_projection(projection);
+}
+
+/// Moves the cursor back to the middle of the window, so that mouse-look never stops at the edge
+/// of the screen.
+fn recenter_cursor(display: &glium::Display, mouse: &mut MouseState) {
+    if let Some(window) = display.get_window() {
+        if let Some((width, height)) = window.get_inner_size_points() {
+            let (x, y) = (width as i32 / 2, height as i32 / 2);
+            if window.set_cursor_position(x, y).is_ok() {
+                mouse.warped(x, y);
+            }
+        }
+    }
+}
+
+/// Sets up the renderer for the level being played and announces it. With `autoplay`, returns
+/// the bot that is going to play it.
+fn start_level(game: &mut Game, renderer: &mut Renderer, autoplay: bool) -> Option<Bot> {
+    let mut projection = Projection::default();
+    if let Some(fov) = game.fov() {
+        projection.fov = fov.to_radians() as f32;
+    }
+    renderer.set_projection(projection);
+    if let (Some(number), Some(title)) = (game.level_number(), game.title()) {
+        println!("Level {}: {}", number, title);
+    }
+    if !autoplay {
+        return None;
+    }
+    match Bot::new(game.level_mut()?) {
+        Ok(bot) => Some(bot),
+        Err(err) => {
+            eprintln!("The bot can't play this level: {}", err);
+            None
+        }
+    }
+}
+
+/// Loads the replay at `path`, which has to be recorded on the same levels as `origin`.
+fn load_replay(path: &str, origin: &ReplayOrigin) -> Result<Replay, String> {
+    let replay = Replay::from_file(path).map_err(|err| format!("{}: {}", path, err))?;
+    if replay.step() != simulation::TIME_STEP {
+        return Err(format!(
+            "{}: recorded with a time step of {} s instead of {} s",
+            path,
+            replay.step(),
+            simulation::TIME_STEP
+        ));
+    }
+    replay
+        .origin()
+        .check(origin)
+        .map_err(|err| format!("{}: {}", path, err))?;
+    Ok(replay)
+}
+
+fn save_recording(game: &Game, path: &str) -> Result<(), String> {
+    if let Some(recorder) = game.recording() {
+        let file = File::create(path).map_err(|err| format!("{}: {}", path, err))?;
+        recorder
+            .write_to(BufWriter::new(file))
+            .map_err(|err| format!("{}: {}", path, err))?;
+    }
+    Ok(())
+}
+
+/// Continues the game from the file at `path`, if there is one.
+fn resume_game(game: &mut Game, path: &str) -> Result<(), String> {
+    if !Path::new(path).exists() {
+        return Ok(());
+    }
+    let saved = SavedGame::from_file(path).map_err(|err| format!("{}: {}", path, err))?;
+    game.resume(&saved)
+        .map_err(|err| format!("{}: {}", path, err))?;
+    println!("Continuing level {} from {:.1} s", saved.level, saved.time);
+    Ok(())
+}
+
+/// Saves the progress to the file at `path`, or removes the file if the game is finished.
+fn save_game(game: &mut Game, path: &str) -> Result<(), String> {
+    match game.save() {
+        Some(saved) => {
+            let file = File::create(path).map_err(|err| format!("{}: {}", path, err))?;
+            saved
+                .write_to(BufWriter::new(file))
+                .map_err(|err| format!("{}: {}", path, err))
+        }
+        None if Path::new(path).exists() => {
+            fs::remove_file(path).map_err(|err| format!("{}: {}", path, err))
+        }
+        None => Ok(()),
     }
 }
 
 fn main() {
-    let levels = ["level1.dat", "level2.dat"];
+    let options = match Options::from_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            return;
+        }
+    };
+    let (stages, maze) = match load_stages(&options) {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        }
+    };
+
+    let mut bindings = match options.bindings {
+        Some(ref path) => match Bindings::from_file(path) {
+            Ok(bindings) => bindings,
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                return;
+            }
+        },
+        None => Bindings::default(),
+    };
+    if options.mouse_look {
+        bindings.enable_mouse_look();
+    }
+    // a missing default device just means there's no gamepad
+    let joystick = match options.joystick {
+        Some(ref device) => match Joystick::open(device) {
+            Ok(joystick) => Some(joystick),
+            Err(err) => {
+                eprintln!("{}: {}", device, err);
+                return;
+            }
+        },
+        None => Joystick::open(joystick::DEFAULT_DEVICE).ok(),
+    };
+
+    let mut game = Game::new(stages);
+    let origin = ReplayOrigin {
+        levels: game.levels(),
+        maze: maze,
+    };
+    let mut replay = match options.replay {
+        Some(ref path) => match load_replay(path, &origin) {
+            Ok(replay) => Some(replay),
+            Err(err) => {
+                eprintln!("{}", err);
+                return;
+            }
+        },
+        None => None,
+    };
 
     let display = glium::glutin::WindowBuilder::new()
         .with_depth_buffer(24)
         .build_glium()
         .unwrap();
-    let mut renderer = graph4d::renderer::Renderer::new(&display);
+    let mut renderer = Renderer::new(&display);
     let mut keyboard = KeyboardState::new();
+    let mut analog = AnalogState::new();
+    let mut mouse = MouseState::new();
+    let mut focused = true;
+    let mouse_look = bindings.mouse_look().enabled;
+    if mouse_look {
+        if let Some(window) = display.get_window() {
+            let _ = window.set_cursor_state(CursorState::Hide);
+        }
+    }
 
-    let mut now = SystemTime::now();
-    let mut level_num = 1u8;
+    if options.record.is_some() {
+        game.start_recording(origin);
+    }
+    if let Some(ref path) = options.resume {
+        if let Err(err) = resume_game(&mut game, path) {
+            eprintln!("{}", err);
+            return;
+        }
+    }
+    let mut bot = start_level(&mut game, &mut renderer, options.autoplay);
+    let mut last_frame = Instant::now();
 
-    for level_file in &levels {
-        let mut level = Level::from_file(level_file).unwrap();
+    'game: while let Some(level) = game.level_mut() {
+        let mut target = display.draw();
+        target.clear_color_and_depth((0.0, 0.0, 0.0, 1.0), 1.0);
+        let hyperplane = level.player().get_hyperplane();
+        for object in level.visible_objects(hyperplane) {
+            object.draw(&mut renderer);
+        }
+        renderer.render(&display, &*level.player(), &mut target);
+        target.finish().unwrap();
 
-        loop {
-            let mut target = display.draw();
-            target.clear_color_and_depth((0.0, 0.0, 0.0, 1.0), 1.0);
-            for object in level.game_objects() {
-                object.draw(&mut renderer);
-            }
-            renderer.render(&display, &*level.player(), &mut target);
-            target.finish().unwrap();
-
-            // listing the events produced by the window and waiting to be received
-            for ev in display.poll_events() {
-                match ev {
-                    glium::glutin::Event::Closed => return, // the window has been closed by the user
-                    glium::glutin::Event::KeyboardInput(state, _, Some(key)) => match state {
-                        ElementState::Pressed => keyboard.pressed(key),
-                        ElementState::Released => keyboard.released(key),
-                    },
-                    _ => (),
-                }
+        // listing the events produced by the window and waiting to be received
+        for ev in display.poll_events() {
+            match ev {
+                glium::glutin::Event::Closed => break 'game, // the window has been closed by the user
+                glium::glutin::Event::KeyboardInput(state, _, Some(key)) => match state {
+                    ElementState::Pressed => {
+                        keyboard.pressed(key);
+                        zoom(key, &mut renderer, level);
+                        if key == VirtualKeyCode::F3 {
+                            let stats = renderer.stats();
+                            println!(
+                                "{} of {} objects culled, {} of {} primitives sliced, {} visible",
+                                stats.objects_culled,
+                                stats.objects,
+                                stats.primitives_sliced,
+                                stats.primitives,
+                                stats.primitives_visible
+                            );
+                        }
+                    }
+                    ElementState::Released => keyboard.released(key),
+                },
+                glium::glutin::Event::MouseMoved(x, y) => mouse.moved(x, y),
+                glium::glutin::Event::Focused(state) => focused = state,
+                _ => (),
             }
+        }
 
-            let frame_time = now.elapsed().unwrap();
-            let frame_time = frame_time.as_secs() as f64 + (frame_time.subsec_nanos() as f64) / 1e9;
-            now = SystemTime::now();
+        let frame_start = Instant::now();
+        let frame_time = frame_start - last_frame;
+        let frame_time = frame_time.as_secs() as f64 + (frame_time.subsec_nanos() as f64) / 1e9;
+        last_frame = frame_start;
 
-            let action = level.player().handle_input(&keyboard, frame_time);
-            let mut let_move = true;
-            for c in level.collidables() {
-                if c.collides(&action) {
-                    let_move = false;
-                }
+        if let Some(ref joystick) = joystick {
+            joystick.update(&mut analog);
+        }
+        let result = match (&mut replay, &mut bot) {
+            (&mut Some(ref mut replay), _) => {
+                game.update_with(frame_time, |_| replay.next_actions().unwrap_or_default())
             }
-            if level.wins(&action) {
-                println!("Level {} completed!", level_num);
-                level_num += 1;
-                break;
+            (_, &mut Some(ref mut bot)) => {
+                game.update_with(frame_time, |player| bot.actions(player))
             }
-            if let_move {
-                level.player().perform_action(action);
+            _ => game.update(frame_time, &bindings.actions(&keyboard, &analog, &mouse)),
+        };
+        // the player takes over where the replay ends
+        if replay.as_ref().map_or(false, Replay::is_finished) {
+            println!("End of the replay");
+            replay = None;
+        }
+        mouse.clear_motion();
+        if mouse_look && focused {
+            recenter_cursor(&display, &mut mouse);
+        }
+
+        if let Some(result) = result {
+            match result.par {
+                Some(par) => println!(
+                    "Level {} completed in {:.1} s (par {} s)!",
+                    result.number, result.time, par
+                ),
+                None => println!("Level {} completed in {:.1} s!", result.number, result.time),
             }
+            if !game.is_finished() {
+                bot = start_level(&mut game, &mut renderer, options.autoplay);
+            }
+        }
+    }
+
+    if let Some(ref path) = options.record {
+        if let Err(err) = save_recording(&game, path) {
+            eprintln!("{}", err);
+        }
+    }
+    if let Some(ref path) = options.resume {
+        if let Err(err) = save_game(&mut game, path) {
+            eprintln!("{}", err);
         }
     }
 }
@@ -1,7 +1,6 @@
-use super::KeyboardState;
-use glium::glutin::VirtualKeyCode;
+use super::input::{ActionState, Axis};
 use graph4d::camera::Camera;
-use graph4d::geometry::{Hyperplane, Matrix, Vector};
+use graph4d::geometry::{Aabb, Contact, Hyperplane, Matrix, Vector};
 use graph4d::primitive::Color;
 use graph4d::renderer::Renderer;
 use std::f64::consts::PI;
@@ -12,15 +11,45 @@ pub trait GameObject {
 
 pub trait Collidable {
     fn collides(&self, action: &AdditionalAction) -> bool;
+
+    /// Finds where the player, moving from `start` by `motion`, first runs into the object.
+    fn sweep(&self, start: Vector, motion: Vector) -> Option<Contact>;
+
+    /// A box containing every position of the player that collides with the object.
+    fn bounds(&self) -> Aabb;
 }
 
 const SIZE: f64 = 0.4;
 
+/// Where the camera is placed by default, relative to the player's orientation - a bit above
+/// and behind.
+const CAMERA_OFFSET: [f64; 4] = [0.0, 0.7, -3.0, 0.0];
+
+/// How fast the player moves, in units per second.
+pub const SPEED: f64 = 3.2;
+/// How fast the player turns, in radians per second.
+const ANGULAR_SPEED: f64 = 1.05;
+/// How far the player's orientation may drift from a rotation before it gets corrected. With
+/// the rounding errors of `f64`, this takes thousands of rotations.
+const MAX_DRIFT: f64 = 1e-12;
+
 pub enum AdditionalAction {
     None,
     MoveTo(Vector),
 }
 
+/// Everything needed to put the player back exactly where they were: the position, the
+/// directions of their axes and the orientation of their tesseract.
+#[derive(Clone, Copy)]
+pub struct Pose {
+    pub position: Vector,
+    pub right: Vector,
+    pub up: Vector,
+    pub front: Vector,
+    pub ana: Vector,
+    pub orientation: Matrix,
+}
+
 pub struct Player {
     up: Vector,
     front: Vector,
@@ -28,6 +57,7 @@ pub struct Player {
     ana: Vector,
     position: Vector,
     orientation: Matrix,
+    camera_offset: Vector,
 }
 
 impl Player {
@@ -39,9 +69,85 @@ impl Player {
             ana: Vector::new(0.0, 0.0, 0.0, 1.0),
             position: Vector::new(0.0, 0.0, 0.0, 0.0),
             orientation: Matrix::identity(),
+            camera_offset: Vector::new(
+                CAMERA_OFFSET[0],
+                CAMERA_OFFSET[1],
+                CAMERA_OFFSET[2],
+                CAMERA_OFFSET[3],
+            ),
+        }
+    }
+
+    /// Creates a player at `position`, with the given directions of their own axes. The
+    /// directions must be orthonormal.
+    pub fn with_pose(
+        position: Vector,
+        right: Vector,
+        up: Vector,
+        front: Vector,
+        ana: Vector,
+    ) -> Player {
+        let mut orientation = [[0.0; 5]; 5];
+        for (i, row) in orientation.iter_mut().enumerate().take(4) {
+            *row = [
+                right.coord(i),
+                up.coord(i),
+                front.coord(i),
+                ana.coord(i),
+                0.0,
+            ];
+        }
+        orientation[4][4] = 1.0;
+        Player {
+            up: up,
+            front: front,
+            right: right,
+            ana: ana,
+            position: position,
+            orientation: Matrix::from_array(orientation),
+            ..Player::new()
+        }
+    }
+
+    pub fn position(&self) -> Vector {
+        self.position
+    }
+
+    pub fn pose(&self) -> Pose {
+        Pose {
+            position: self.position,
+            right: self.right,
+            up: self.up,
+            front: self.front,
+            ana: self.ana,
+            orientation: self.orientation,
         }
     }
 
+    pub fn set_pose(&mut self, pose: &Pose) {
+        self.position = pose.position;
+        self.right = pose.right;
+        self.up = pose.up;
+        self.front = pose.front;
+        self.ana = pose.ana;
+        self.orientation = pose.orientation;
+    }
+
+    /// The directions of the player's own axes: right, up, front and ana.
+    pub fn axes(&self) -> [Vector; 4] {
+        [self.right, self.up, self.front, self.ana]
+    }
+
+    /// The position of the third-person camera relative to the player, in the player's own
+    /// coordinates (right, up, front, ana).
+    pub fn camera_offset(&self) -> Vector {
+        self.camera_offset
+    }
+
+    pub fn set_camera_offset(&mut self, offset: Vector) {
+        self.camera_offset = offset;
+    }
+
     pub fn go(&mut self, dir: Vector) {
         self.position = self.position
             + self.up * dir.y()
@@ -56,6 +162,23 @@ impl Player {
         self.front = (matrix * self.front).normalized();
         self.ana = (matrix * self.ana).normalized();
         self.orientation = matrix * self.orientation;
+        // the check only depends on the orientation, so a restored pose is corrected at the same
+        // moments as the original one
+        if !self.orientation.is_orthonormal(MAX_DRIFT) {
+            self.orthonormalize();
+        }
+    }
+
+    /// Fixes the drift of the orientation and sets the axes to its columns, so that they are
+    /// orthonormal and agree with it again.
+    fn orthonormalize(&mut self) {
+        self.orientation = self.orientation.orthonormalized();
+        let coords = self.orientation.as_array();
+        let column = |j: usize| Vector::new(coords[0][j], coords[1][j], coords[2][j], coords[3][j]);
+        self.right = column(0);
+        self.up = column(1);
+        self.front = column(2);
+        self.ana = column(3);
     }
 
     pub fn rotate_xy(&mut self, phi: f64) {
@@ -88,71 +211,35 @@ impl Player {
         self.apply_matrix(matrix);
     }
 
-    pub fn handle_input(&mut self, keyboard: &KeyboardState, time: f64) -> AdditionalAction {
-        let vel = 3.2;
-        let angvel = 1.05;
-
-        let distance = vel * time;
-        let angle = angvel * time;
+    pub fn handle_input(&mut self, actions: &ActionState, time: f64) -> AdditionalAction {
+        let distance = SPEED * time;
+        let angle = ANGULAR_SPEED * time;
 
         let old_position = self.position;
 
         // movements
-        if keyboard.is_pressed(VirtualKeyCode::W) {
-            self.go(Vector::new(0.0, 0.0, distance, 0.0));
-        }
-        if keyboard.is_pressed(VirtualKeyCode::S) {
-            self.go(Vector::new(0.0, 0.0, -distance, 0.0));
-        }
-        if keyboard.is_pressed(VirtualKeyCode::A) {
-            self.go(Vector::new(-distance, 0.0, 0.0, 0.0));
-        }
-        if keyboard.is_pressed(VirtualKeyCode::D) {
-            self.go(Vector::new(distance, 0.0, 0.0, 0.0));
-        }
-        if keyboard.is_pressed(VirtualKeyCode::Q) {
-            self.go(Vector::new(0.0, distance, 0.0, 0.0));
-        }
-        if keyboard.is_pressed(VirtualKeyCode::E) {
-            self.go(Vector::new(0.0, -distance, 0.0, 0.0));
-        }
+        let movement = Vector::new(
+            actions.axis(Axis::MoveRight),
+            actions.axis(Axis::MoveUp),
+            actions.axis(Axis::MoveForward),
+            actions.axis(Axis::MoveAna),
+        );
+        self.go(movement * distance);
 
         // rotations
-        if keyboard.is_pressed(VirtualKeyCode::T) {
-            self.rotate_xw(angle);
-        }
-        if keyboard.is_pressed(VirtualKeyCode::G) {
-            self.rotate_xw(-angle);
-        }
-        if keyboard.is_pressed(VirtualKeyCode::F) {
-            self.rotate_zw(angle);
-        }
-        if keyboard.is_pressed(VirtualKeyCode::H) {
-            self.rotate_zw(-angle);
-        }
-        if keyboard.is_pressed(VirtualKeyCode::R) {
-            self.rotate_yw(-angle);
-        }
-        if keyboard.is_pressed(VirtualKeyCode::Y) {
-            self.rotate_yw(angle);
-        }
-        if keyboard.is_pressed(VirtualKeyCode::U) {
-            self.rotate_xy(-angle);
-        }
-        if keyboard.is_pressed(VirtualKeyCode::J) {
-            self.rotate_xy(angle);
-        }
-        if keyboard.is_pressed(VirtualKeyCode::C) {
-            self.rotate_xz(angle);
-        }
-        if keyboard.is_pressed(VirtualKeyCode::V) {
-            self.rotate_xz(-angle);
-        }
-        if keyboard.is_pressed(VirtualKeyCode::B) {
-            self.rotate_yz(angle);
-        }
-        if keyboard.is_pressed(VirtualKeyCode::N) {
-            self.rotate_yz(-angle);
+        let rotations: [(Axis, fn(&mut Player, f64)); 6] = [
+            (Axis::RotateXW, Player::rotate_xw),
+            (Axis::RotateZW, Player::rotate_zw),
+            (Axis::RotateYW, Player::rotate_yw),
+            (Axis::RotateXY, Player::rotate_xy),
+            (Axis::RotateXZ, Player::rotate_xz),
+            (Axis::RotateYZ, Player::rotate_yz),
+        ];
+        for &(axis, rotate) in rotations.iter() {
+            let phi = angle * actions.axis(axis) + actions.turned(axis);
+            if phi != 0.0 {
+                rotate(self, phi);
+            }
         }
 
         if self.position != old_position {
@@ -189,7 +276,7 @@ impl Camera for Player {
     }
 
     fn calculate_local(&self, vec: Vector) -> Vector {
-        let dir = vec - self.position - self.orientation * Vector::new(0.0, 0.7, -3.0, 0.0);
+        let dir = vec - self.position - self.orientation * self.camera_offset;
         let x = dir.dot(self.right);
         let y = dir.dot(self.up);
         let z = dir.dot(self.front);
@@ -198,46 +285,71 @@ impl Camera for Player {
     }
 }
 
+/// A flat box - a 3D cuboid placed somewhere in the 4D space. In its own coordinates the wall
+/// spans its extents along x, y and z, and has no thickness along w.
 pub struct Wall {
-    middle: Vector,
-    size: Vector,
+    center: Vector,
+    // the wall's own x, y, z and w axes in the world coordinates
+    axes: [Vector; 4],
+    extents: [f64; 4],
     transformation_matrix: Matrix,
 }
 
 impl Wall {
+    /// Creates an axis-aligned wall. Exactly one component of `size` must be zero - the wall
+    /// is perpendicular to that axis.
     pub fn new(middle: Vector, size: Vector) -> Wall {
         let pi_2 = PI / 2.0;
-        let rotation_matrix = if size.w() == 0.0 {
-            Matrix::identity()
-        } else if size.x() == 0.0 {
-            Matrix::rotation_xw(pi_2)
-        } else if size.y() == 0.0 {
-            Matrix::rotation_yw(pi_2)
-        } else if size.z() == 0.0 {
-            Matrix::rotation_zw(pi_2)
-        } else {
-            panic!("Wall without a zero dimension!")
-        };
-        let scale_matrix = if size.w() == 0.0 {
-            Matrix::scale(size.x(), size.y(), size.z(), 1.0)
+        let (rotation_matrix, extents) = if size.w() == 0.0 {
+            (Matrix::identity(), [size.x(), size.y(), size.z()])
         } else if size.x() == 0.0 {
-            Matrix::scale(size.w(), size.y(), size.z(), 1.0)
+            (Matrix::rotation_xw(pi_2), [size.w(), size.y(), size.z()])
         } else if size.y() == 0.0 {
-            Matrix::scale(size.x(), size.w(), size.z(), 1.0)
+            (Matrix::rotation_yw(pi_2), [size.x(), size.w(), size.z()])
         } else if size.z() == 0.0 {
-            Matrix::scale(size.x(), size.y(), size.w(), 1.0)
+            (Matrix::rotation_zw(pi_2), [size.x(), size.y(), size.w()])
         } else {
             panic!("Wall without a zero dimension!")
         };
-        let translation_matrix = Matrix::translation(middle);
+        Wall::oriented(middle, rotation_matrix, extents)
+    }
+
+    /// Creates a wall with its center at `center`, turned by the rotation `orientation` from
+    /// the position where it spans `extents` along the x, y and z axes.
+    pub fn oriented(center: Vector, orientation: Matrix, extents: [f64; 3]) -> Wall {
+        let basis = [
+            Vector::new(1.0, 0.0, 0.0, 0.0),
+            Vector::new(0.0, 1.0, 0.0, 0.0),
+            Vector::new(0.0, 0.0, 1.0, 0.0),
+            Vector::new(0.0, 0.0, 0.0, 1.0),
+        ];
+        let mut axes = basis;
+        for (axis, &base) in axes.iter_mut().zip(basis.iter()) {
+            *axis = orientation * base;
+        }
+        let scale_matrix = Matrix::scale(extents[0], extents[1], extents[2], 1.0);
         Wall {
-            middle: middle,
-            size: size,
-            transformation_matrix: translation_matrix * rotation_matrix * scale_matrix,
+            center: center,
+            axes: axes,
+            extents: [extents[0], extents[1], extents[2], 0.0],
+            transformation_matrix: Matrix::translation(center) * orientation * scale_matrix,
         }
     }
 }
 
+impl Wall {
+    /// Half the size of the wall grown by the player's size, along each of the wall's axes.
+    fn local_half_size(&self) -> [f64; 4] {
+        let mut half_size = [0.0; 4];
+        for (i, half) in half_size.iter_mut().enumerate() {
+            let axis = self.axes[i];
+            *half = self.extents[i] / 2.0
+                + SIZE / 2.0 * (0..4).map(|k| axis.coord(k).abs()).sum::<f64>();
+        }
+        half_size
+    }
+}
+
 impl GameObject for Wall {
     fn draw(&self, renderer: &mut Renderer) {
         renderer.set_color(Color::rgba(0.6, 0.6, 0.6, 0.2));
@@ -249,19 +361,82 @@ impl GameObject for Wall {
 }
 
 impl Collidable for Wall {
+    /// Checks whether the player's box overlaps the wall, by looking for a gap between their
+    /// projections onto the axes of both the wall and the world. For axis-aligned walls this
+    /// is exact; near the edges of slanted walls it can report a touch slightly too early.
     fn collides(&self, action: &AdditionalAction) -> bool {
         match *action {
             AdditionalAction::MoveTo(pos) => {
-                let x = (pos.x() - self.middle.x()).abs() < (self.size.x() + SIZE) / 2.0;
-                let y = (pos.y() - self.middle.y()).abs() < (self.size.y() + SIZE) / 2.0;
-                let z = (pos.z() - self.middle.z()).abs() < (self.size.z() + SIZE) / 2.0;
-                let w = (pos.w() - self.middle.w()).abs() < (self.size.w() + SIZE) / 2.0;
-
-                x && y && z && w
+                let offset = pos - self.center;
+
+                // the wall's own axes, against which the player's box looks bigger when slanted
+                let wall_axes = self
+                    .axes
+                    .iter()
+                    .zip(self.extents.iter())
+                    .all(|(axis, ext)| {
+                        let player = SIZE / 2.0 * (0..4).map(|k| axis.coord(k).abs()).sum::<f64>();
+                        offset.dot(*axis).abs() < ext / 2.0 + player
+                    });
+
+                // the world axes, against which the wall looks bigger when slanted
+                let world_axes = (0..4).all(|k| {
+                    let wall: f64 = self
+                        .axes
+                        .iter()
+                        .zip(self.extents.iter())
+                        .map(|(axis, ext)| ext / 2.0 * axis.coord(k).abs())
+                        .sum();
+                    offset.coord(k).abs() < wall + SIZE / 2.0
+                });
+
+                wall_axes && world_axes
             }
             _ => false,
         }
     }
+
+    /// Sweeps the player against the wall in the wall's own coordinates, where the wall is an
+    /// axis-aligned box grown by the player's size.
+    fn sweep(&self, start: Vector, motion: Vector) -> Option<Contact> {
+        let offset = start - self.center;
+        let mut local_start = [0.0; 4];
+        let mut local_motion = [0.0; 4];
+        for i in 0..4 {
+            local_start[i] = offset.dot(self.axes[i]);
+            local_motion[i] = motion.dot(self.axes[i]);
+        }
+        let half_size = self.local_half_size();
+        let vector = |c: [f64; 4]| Vector::new(c[0], c[1], c[2], c[3]);
+
+        let local_box = Aabb::from_center(Vector::new(0.0, 0.0, 0.0, 0.0), vector(half_size));
+        local_box
+            .sweep(vector(local_start), vector(local_motion))
+            .map(|contact| {
+                let n = contact.normal;
+                Contact {
+                    time: contact.time,
+                    normal: self.axes[0] * n.x()
+                        + self.axes[1] * n.y()
+                        + self.axes[2] * n.z()
+                        + self.axes[3] * n.w(),
+                }
+            })
+    }
+
+    fn bounds(&self) -> Aabb {
+        let half_size = self.local_half_size();
+        let mut world = [0.0; 4];
+        for (k, w) in world.iter_mut().enumerate() {
+            *w = (0..4)
+                .map(|i| half_size[i] * self.axes[i].coord(k).abs())
+                .sum();
+        }
+        Aabb::from_center(
+            self.center,
+            Vector::new(world[0], world[1], world[2], world[3]),
+        )
+    }
 }
 
 pub struct Target {
@@ -276,6 +451,10 @@ impl Target {
             size: size,
         }
     }
+
+    pub fn position(&self) -> Vector {
+        self.position
+    }
 }
 
 impl GameObject for Target {
@@ -302,4 +481,149 @@ impl Collidable for Target {
             _ => false,
         }
     }
+
+    fn sweep(&self, start: Vector, motion: Vector) -> Option<Contact> {
+        self.bounds().sweep(start, motion)
+    }
+
+    fn bounds(&self) -> Aabb {
+        let half = (self.size + SIZE) / 2.0;
+        Aabb::from_center(self.position, Vector::new(half, half, half, half))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::input::{Action, ActionState, Axis};
+    use super::{AdditionalAction, Collidable, Player, Wall, MAX_DRIFT};
+    use graph4d::geometry::{Matrix, Vector};
+    use std::f64::consts::PI;
+
+    fn collides(wall: &Wall, x: f64, y: f64, z: f64, w: f64) -> bool {
+        wall.collides(&AdditionalAction::MoveTo(Vector::new(x, y, z, w)))
+    }
+
+    #[test]
+    fn test_axis_aligned_wall() {
+        // a wall at x = 2.5, 5 units wide in y, z and w
+        let wall = Wall::new(
+            Vector::new(2.5, 0.0, 0.0, 0.0),
+            Vector::new(0.0, 5.0, 5.0, 5.0),
+        );
+        assert!(!collides(&wall, 2.0, 0.0, 0.0, 0.0));
+        assert!(collides(&wall, 2.4, 0.0, 0.0, 0.0));
+        assert!(collides(&wall, 2.6, 2.6, -2.6, 2.6));
+        assert!(!collides(&wall, 2.5, 0.0, 0.0, 2.8));
+    }
+
+    #[test]
+    fn test_slanted_wall() {
+        // a ramp: a wall perpendicular to w, tilted by 45 degrees towards z
+        let wall = Wall::oriented(
+            Vector::new(0.0, 0.0, 0.0, 0.0),
+            Matrix::rotation_zw(PI / 4.0),
+            [4.0, 4.0, 4.0],
+        );
+        // the wall lies along the z = w diagonal
+        assert!(collides(&wall, 0.0, 0.0, 1.0, 1.0));
+        assert!(collides(&wall, 0.0, 0.0, -1.0, -1.0));
+        assert!(!collides(&wall, 0.0, 0.0, 1.0, -1.0));
+        assert!(!collides(&wall, 0.0, 0.0, -1.0, 1.0));
+        // an axis-aligned box around the same center would have caught this point
+        assert!(!collides(&wall, 0.0, 0.0, 0.0, 1.0));
+        // beyond the end of the ramp
+        assert!(!collides(&wall, 0.0, 0.0, 1.9, 1.9));
+    }
+
+    #[test]
+    fn test_sweep_slanted_wall() {
+        let wall = Wall::oriented(
+            Vector::new(0.0, 0.0, 0.0, 0.0),
+            Matrix::rotation_zw(PI / 4.0),
+            [4.0, 4.0, 4.0],
+        );
+        // falling along -w onto the ramp
+        let contact = wall
+            .sweep(
+                Vector::new(0.0, 0.0, 1.0, 3.0),
+                Vector::new(0.0, 0.0, 0.0, -4.0),
+            )
+            .unwrap();
+        let normal = Vector::new(0.0, 0.0, -1.0, 1.0) / 2f64.sqrt();
+        assert!(contact.normal == normal);
+        // the player's center stops above the ramp, at the distance of its projected size
+        let stop = Vector::new(0.0, 0.0, 1.0, 3.0 - 4.0 * contact.time);
+        let distance = stop.dot(normal);
+        assert!((distance - 0.2 * 2f64.sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_handle_input() {
+        let mut player = Player::new();
+        let mut actions = ActionState::new();
+        actions.set(Action::MoveForward, true);
+        actions.set(Action::MoveRight, true);
+        match player.handle_input(&actions, 0.5) {
+            AdditionalAction::MoveTo(position) => {
+                assert!(position == player.position() + Vector::new(1.6, 0.0, 1.6, 0.0))
+            }
+            _ => panic!("the player should move"),
+        }
+
+        // only the actions move the player, and rotations are applied immediately
+        let mut actions = ActionState::new();
+        actions.set(Action::RotateXZPlus, true);
+        match player.handle_input(&actions, 0.5) {
+            AdditionalAction::None => (),
+            _ => panic!("the player shouldn't move"),
+        }
+    }
+
+    #[test]
+    fn test_turn() {
+        // a quarter turn to the right, the same however long the frame took
+        let mut player = Player::new();
+        let mut actions = ActionState::new();
+        actions.turn(Axis::RotateYW, PI / 2.0);
+        player.handle_input(&actions, 0.0);
+
+        let mut actions = ActionState::new();
+        actions.set(Action::MoveForward, true);
+        match player.handle_input(&actions, 1.0) {
+            AdditionalAction::MoveTo(position) => {
+                assert!(position == player.position() + Vector::new(3.2, 0.0, 0.0, 0.0))
+            }
+            _ => panic!("the player should move"),
+        }
+    }
+
+    #[test]
+    fn test_no_drift() {
+        let mut player = Player::new();
+        for i in 0..10000 {
+            let phi = 0.1 + (i % 7) as f64 * 0.37;
+            match i % 6 {
+                0 => player.rotate_xy(phi),
+                1 => player.rotate_xz(phi),
+                2 => player.rotate_xw(phi),
+                3 => player.rotate_yz(phi),
+                4 => player.rotate_yw(phi),
+                _ => player.rotate_zw(phi),
+            }
+        }
+
+        let axes = player.axes();
+        let orientation = player.orientation.as_array();
+        assert!(player.orientation.is_orthonormal(MAX_DRIFT));
+        for (i, axis) in axes.iter().enumerate() {
+            for (j, other) in axes.iter().enumerate() {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((axis.dot(*other) - expected).abs() < 1e-9);
+            }
+            // the orientation still turns the player's tesseract the same way as the axes
+            for k in 0..4 {
+                assert!((orientation[k][i] - axis.coord(k)).abs() < 1e-9);
+            }
+        }
+    }
 }
@@ -0,0 +1,399 @@
+use super::levels::{Level, LevelError};
+use graph4d::geometry::Vector;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+/// The pack played when no other is given. Its levels are built into the executable, so the
+/// game can be started from any directory.
+const BUILTIN_MANIFEST: &str = include_str!("../levels.pack");
+const BUILTIN_LEVELS: [(&str, &str); 2] = [
+    ("level1.dat", include_str!("../level1.dat")),
+    ("level2.dat", include_str!("../level2.dat")),
+];
+
+/// The keys a level of the manifest can set.
+const KEYS: [&str; 5] = ["file", "title", "par", "fov", "camera"];
+
+/// Everything that can go wrong while reading a level pack manifest.
+///
+/// Line numbers are 1-based and refer to the line of the manifest the error was found on.
+#[derive(Debug)]
+pub enum PackError {
+    Io(io::Error),
+    Syntax {
+        line: usize,
+        text: String,
+    },
+    UnknownKey {
+        line: usize,
+        key: String,
+    },
+    DuplicateKey {
+        line: usize,
+        key: String,
+    },
+    BadValue {
+        line: usize,
+        key: String,
+        value: String,
+    },
+    MissingFile {
+        line: usize,
+    },
+    NoLevels,
+}
+
+impl Display for PackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PackError::Io(ref err) => write!(f, "I/O error: {}", err),
+            PackError::Syntax { line, ref text } => write!(
+                f,
+                "line {}: expected `[level]` or `key = value`, found `{}`",
+                line, text
+            ),
+            PackError::UnknownKey { line, ref key } => write!(
+                f,
+                "line {}: unknown key `{}` (expected file, title, par, fov or camera)",
+                line, key
+            ),
+            PackError::DuplicateKey { line, ref key } => write!(
+                f,
+                "line {}: `{}` is given more than once for the same level",
+                line, key
+            ),
+            PackError::BadValue {
+                line,
+                ref key,
+                ref value,
+            } => write!(f, "line {}: invalid {} `{}`", line, key, value),
+            PackError::MissingFile { line } => {
+                write!(f, "line {}: the level doesn't name its file", line)
+            }
+            PackError::NoLevels => write!(f, "the pack has no levels"),
+        }
+    }
+}
+
+impl Error for PackError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            PackError::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PackError {
+    fn from(err: io::Error) -> PackError {
+        PackError::Io(err)
+    }
+}
+
+/// Per-level overrides of the game settings.
+#[derive(Default)]
+pub struct LevelSettings {
+    /// Vertical field of view in degrees.
+    pub fov: Option<f64>,
+    /// Position of the camera relative to the player.
+    pub camera: Option<Vector>,
+}
+
+/// A single level of a pack.
+pub struct PackEntry {
+    file: PathBuf,
+    title: Option<String>,
+    par: Option<f64>,
+    settings: LevelSettings,
+    builtin: Option<&'static str>,
+}
+
+impl PackEntry {
+    fn new(file: PathBuf) -> PackEntry {
+        PackEntry {
+            file: file,
+            title: None,
+            par: None,
+            settings: Default::default(),
+            builtin: None,
+        }
+    }
+
+    pub fn file(&self) -> &Path {
+        &self.file
+    }
+
+    /// The title given in the manifest, or the name of the level file.
+    pub fn title(&self) -> String {
+        match self.title {
+            Some(ref title) => title.clone(),
+            None => self
+                .file
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// The expected time to complete the level, in seconds.
+    pub fn par(&self) -> Option<f64> {
+        self.par
+    }
+
+    pub fn settings(&self) -> &LevelSettings {
+        &self.settings
+    }
+
+    pub fn load(&self) -> Result<Level, LevelError> {
+        match self.builtin {
+            Some(text) => text.parse(),
+            None => Level::from_file(&self.file),
+        }
+    }
+}
+
+/// An ordered list of levels, read from a manifest like this:
+///
+/// ```text
+/// # comment
+/// [level]
+/// file = level1.dat
+/// title = First steps
+/// par = 60
+/// fov = 75
+/// camera = 0 1 -5 0
+/// ```
+///
+/// Every level starts with a `[level]` line and needs a `file`; the other keys are optional.
+/// Level files are looked up relative to the directory of the manifest.
+pub struct Pack {
+    entries: Vec<PackEntry>,
+}
+
+impl Pack {
+    pub fn from_file<P: AsRef<Path>>(file_name: P) -> Result<Pack, PackError> {
+        let file_name = file_name.as_ref();
+        let f = File::open(file_name)?;
+        let dir = file_name.parent().unwrap_or_else(|| Path::new(""));
+        Pack::from_reader(BufReader::new(f), dir)
+    }
+
+    /// Reads a manifest, resolving the level files relative to `dir`.
+    pub fn from_reader<R: BufRead>(reader: R, dir: &Path) -> Result<Pack, PackError> {
+        // the entries, with the line numbers of their headers
+        let mut entries: Vec<(usize, PackEntry)> = Vec::new();
+        // the keys already given for the last entry, in the order of `KEYS`
+        let mut seen = [false; 5];
+
+        for (index, line) in reader.lines().enumerate() {
+            let line_num = index + 1;
+            let line = line?;
+            let text = line.trim();
+            if text.is_empty() || text.starts_with('#') {
+                continue;
+            }
+            if text == "[level]" {
+                entries.push((line_num, PackEntry::new(PathBuf::new())));
+                seen = [false; 5];
+                continue;
+            }
+
+            let (key, value) = match (text.find('='), entries.last_mut()) {
+                (Some(pos), Some(_)) => (text[..pos].trim(), text[pos + 1..].trim()),
+                _ => {
+                    return Err(PackError::Syntax {
+                        line: line_num,
+                        text: text.to_owned(),
+                    })
+                }
+            };
+            let key_index =
+                KEYS.iter()
+                    .position(|&k| k == key)
+                    .ok_or_else(|| PackError::UnknownKey {
+                        line: line_num,
+                        key: key.to_owned(),
+                    })?;
+            if seen[key_index] {
+                return Err(PackError::DuplicateKey {
+                    line: line_num,
+                    key: key.to_owned(),
+                });
+            }
+            seen[key_index] = true;
+            let bad_value = || PackError::BadValue {
+                line: line_num,
+                key: key.to_owned(),
+                value: value.to_owned(),
+            };
+            let entry = &mut entries.last_mut().unwrap().1;
+            match key {
+                "file" if !value.is_empty() => entry.file = dir.join(value),
+                "title" if !value.is_empty() => entry.title = Some(value.to_owned()),
+                "par" => entry.par = Some(parse_positive(value).ok_or_else(bad_value)?),
+                "fov" => match parse_positive(value) {
+                    Some(fov) if fov < 180.0 => entry.settings.fov = Some(fov),
+                    _ => return Err(bad_value()),
+                },
+                "camera" => {
+                    let coords: Vec<f64> = value
+                        .split_whitespace()
+                        .map(|c| c.parse())
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| bad_value())?;
+                    if coords.len() != 4 {
+                        return Err(bad_value());
+                    }
+                    entry.settings.camera =
+                        Some(Vector::new(coords[0], coords[1], coords[2], coords[3]));
+                }
+                "file" | "title" => return Err(bad_value()),
+                _ => unreachable!(),
+            }
+        }
+
+        if entries.is_empty() {
+            return Err(PackError::NoLevels);
+        }
+        let mut result = Vec::new();
+        for (line, entry) in entries {
+            if entry.file.as_os_str().is_empty() {
+                return Err(PackError::MissingFile { line: line });
+            }
+            result.push(entry);
+        }
+        Ok(Pack { entries: result })
+    }
+
+    /// A pack consisting of a single level file.
+    pub fn single<P: AsRef<Path>>(file_name: P) -> Pack {
+        Pack {
+            entries: vec![PackEntry::new(file_name.as_ref().to_owned())],
+        }
+    }
+
+    /// The levels shipped with the game.
+    pub fn builtin() -> Pack {
+        let mut pack = Pack::from_reader(BUILTIN_MANIFEST.as_bytes(), Path::new(""))
+            .expect("the built-in manifest is valid");
+        for entry in pack.entries.iter_mut() {
+            entry.builtin = BUILTIN_LEVELS
+                .iter()
+                .find(|&&(name, _)| entry.file == Path::new(name))
+                .map(|&(_, text)| text);
+        }
+        pack
+    }
+
+    pub fn entries(&self) -> &[PackEntry] {
+        &self.entries
+    }
+}
+
+fn parse_positive(value: &str) -> Option<f64> {
+    match value.parse() {
+        Ok(x) if x > 0.0 => Some(x),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Pack, PackError};
+    use graph4d::geometry::Vector;
+    use std::path::Path;
+
+    fn parse(text: &str) -> Result<Pack, PackError> {
+        Pack::from_reader(text.as_bytes(), Path::new("packs/mine"))
+    }
+
+    #[test]
+    fn test_manifest() {
+        let pack = parse(
+            "# two levels\n\
+             [level]\n\
+             file = a.dat\n\
+             title = The first one\n\
+             par = 30.5\n\
+             \n\
+             [level]\n\
+             file = more/b.dat\n\
+             fov = 90\n\
+             camera = 0 1 -5 0\n",
+        )
+        .unwrap();
+        let entries = pack.entries();
+        assert_eq!(entries.len(), 2);
+
+        assert_eq!(entries[0].file(), Path::new("packs/mine/a.dat"));
+        assert_eq!(entries[0].title(), "The first one");
+        assert_eq!(entries[0].par(), Some(30.5));
+        assert!(entries[0].settings().fov.is_none());
+
+        assert_eq!(entries[1].file(), Path::new("packs/mine/more/b.dat"));
+        assert_eq!(entries[1].title(), "b");
+        assert_eq!(entries[1].par(), None);
+        assert_eq!(entries[1].settings().fov, Some(90.0));
+        assert!(entries[1].settings().camera == Some(Vector::new(0.0, 1.0, -5.0, 0.0)));
+    }
+
+    #[test]
+    fn test_title_before_file() {
+        let pack = parse("[level]\ntitle = Named\nfile = x.dat\n").unwrap();
+        assert_eq!(pack.entries()[0].title(), "Named");
+    }
+
+    #[test]
+    fn test_errors() {
+        match parse("file = a.dat\n") {
+            Err(PackError::Syntax { line: 1, .. }) => (),
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+        match parse("[level]\nfile = a.dat\nspeed = 3\n") {
+            Err(PackError::UnknownKey { line: 3, ref key }) if key == "speed" => (),
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+        match parse("[level]\nfile = a.dat\npar = -1\n") {
+            Err(PackError::BadValue {
+                line: 3, ref key, ..
+            }) if key == "par" => (),
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+        match parse("[level]\nfile = a.dat\ncamera = 0 1 2\n") {
+            Err(PackError::BadValue {
+                line: 3, ref key, ..
+            }) if key == "camera" => (),
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+        match parse("[level]\nfile = a.dat\ntitle = A\nfile = b.dat\n") {
+            Err(PackError::DuplicateKey { line: 4, ref key }) if key == "file" => (),
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+        match parse("[level]\nfile = a.dat\ntitle =\n") {
+            Err(PackError::BadValue {
+                line: 3, ref key, ..
+            }) if key == "title" => (),
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+        match parse("[level]\nfile = a.dat\n\n[level]\ntitle = No file\n") {
+            Err(PackError::MissingFile { line: 4 }) => (),
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+        match parse("# nothing\n") {
+            Err(PackError::NoLevels) => (),
+            other => panic!("unexpected result: {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn test_builtin_pack() {
+        let pack = Pack::builtin();
+        assert_eq!(pack.entries().len(), 2);
+        for entry in pack.entries() {
+            assert!(entry.load().is_ok());
+        }
+    }
+}
@@ -0,0 +1,477 @@
+use super::levels::{CellContents, Level};
+use super::objects::{AdditionalAction, Collidable, Wall};
+use graph4d::geometry::{Aabb, Vector};
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::fmt::{self, Display};
+
+/// How far from a face the path goes when crossing into the next cell - a bit more than half
+/// the player's size, so that the player never straddles a wall while turning.
+const CLEARANCE: f64 = 0.5;
+
+/// The finest spacing of the points tried as a door when the middle of a face is blocked. It
+/// is less than the player's size, so no opening wide enough for the player is missed.
+const DOOR_SPACING: f64 = 0.25;
+
+/// The most points tried as a door on a single face. A face of a usual 5-unit cell needs
+/// fewer than that at `DOOR_SPACING`; larger faces aren't searched as finely.
+const MAX_DOORS: usize = 8192;
+
+const EPSILON: f64 = 1e-9;
+
+#[derive(Debug, PartialEq)]
+pub enum PathError {
+    /// The starting point isn't inside any cell of the level.
+    StartOutside,
+    /// The starting point is inside a wall.
+    StartBlocked,
+    /// There is no route from the start to the target.
+    Unreachable,
+}
+
+impl Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PathError::StartOutside => write!(f, "the start is outside of all the corridors"),
+            PathError::StartBlocked => write!(f, "the start is inside a wall"),
+            PathError::Unreachable => write!(f, "the target can't be reached from the start"),
+        }
+    }
+}
+
+fn coords(v: Vector) -> [f64; 4] {
+    [v.x(), v.y(), v.z(), v.w()]
+}
+
+fn from_coords(c: [f64; 4]) -> Vector {
+    Vector::new(c[0], c[1], c[2], c[3])
+}
+
+fn middle(a: [f64; 4], b: [f64; 4]) -> [f64; 4] {
+    [
+        (a[0] + b[0]) / 2.0,
+        (a[1] + b[1]) / 2.0,
+        (a[2] + b[2]) / 2.0,
+        (a[3] + b[3]) / 2.0,
+    ]
+}
+
+/// A way from one cell into another - either through a common face, or through the space
+/// the two cells share.
+struct Passage {
+    to: usize,
+    waypoints: Vec<Vector>,
+}
+
+/// The graph of level cells, connected wherever the player can get from one to the other.
+///
+/// Cells are the boxes of the level file. Two cells are connected if they overlap, or if they
+/// touch and the walls leave the player a way through the face they have in common - through
+/// its middle if possible, through any other part of it otherwise.
+pub struct Pathfinder<'a> {
+    level: &'a Level,
+    boxes: Vec<([f64; 4], [f64; 4])>,
+    passages: Vec<Vec<Passage>>,
+}
+
+impl<'a> Pathfinder<'a> {
+    pub fn new(level: &'a Level) -> Pathfinder<'a> {
+        let boxes: Vec<_> = level
+            .cells()
+            .iter()
+            .map(|cell| (coords(cell.min()), coords(cell.max())))
+            .collect();
+        let mut passages: Vec<Vec<Passage>> = boxes.iter().map(|_| Vec::new()).collect();
+
+        for i in 0..boxes.len() {
+            for j in i + 1..boxes.len() {
+                if let Some(waypoints) = Pathfinder::connect(boxes[i], boxes[j], level.walls()) {
+                    let mut back = waypoints.clone();
+                    back.reverse();
+                    passages[i].push(Passage {
+                        to: j,
+                        waypoints: waypoints,
+                    });
+                    passages[j].push(Passage {
+                        to: i,
+                        waypoints: back,
+                    });
+                }
+            }
+        }
+
+        Pathfinder {
+            level: level,
+            boxes: boxes,
+            passages: passages,
+        }
+    }
+
+    /// Finds the waypoints leading from cell `a` to cell `b`, if the cells are connected.
+    fn connect(
+        a: ([f64; 4], [f64; 4]),
+        b: ([f64; 4], [f64; 4]),
+        walls: &[Wall],
+    ) -> Option<Vec<Vector>> {
+        let mut low = [0.0; 4];
+        let mut high = [0.0; 4];
+        let mut touching_axis = None;
+        for axis in 0..4 {
+            low[axis] = a.0[axis].max(b.0[axis]);
+            high[axis] = a.1[axis].min(b.1[axis]);
+            if (high[axis] - low[axis]).abs() <= EPSILON && touching_axis.is_none() {
+                high[axis] = low[axis];
+                touching_axis = Some(axis);
+            } else if high[axis] - low[axis] <= EPSILON {
+                return None;
+            }
+        }
+
+        let axis = match touching_axis {
+            // the cells share some space
+            None => return Some(vec![from_coords(middle(low, high))]),
+            Some(axis) => axis,
+        };
+
+        // step back from the face into both cells, as far as their thickness allows
+        let direction = if a.1[axis] <= b.0[axis] + EPSILON {
+            1.0
+        } else {
+            -1.0
+        };
+        let back = direction * CLEARANCE.min((a.1[axis] - a.0[axis]) / 2.0);
+        let forward = direction * CLEARANCE.min((b.1[axis] - b.0[axis]) / 2.0);
+
+        // only the walls around the face can be in the way
+        let mut near_low = low;
+        let mut near_high = high;
+        near_low[axis] -= CLEARANCE;
+        near_high[axis] += CLEARANCE;
+        let near = Aabb::new(from_coords(near_low), from_coords(near_high));
+        let walls: Vec<&Wall> = walls
+            .iter()
+            .filter(|wall| wall.bounds().intersects(&near))
+            .collect();
+
+        // the doors lie in a box on the face; as the space a wall blocks is convex, a wall
+        // blocking all the corners of that box blocks every door
+        let (first, last) = Pathfinder::door_area(low, high, axis);
+        let covered = walls.iter().any(|wall| {
+            (0..16).all(|corner| {
+                let mut point = first;
+                for (i, coord) in point.iter_mut().enumerate() {
+                    if corner & (1 << i) != 0 {
+                        *coord = last[i];
+                    }
+                }
+                wall.collides(&AdditionalAction::MoveTo(from_coords(point)))
+            })
+        });
+        if covered {
+            return None;
+        }
+
+        let through = |door: [f64; 4]| {
+            let mut before = door;
+            let mut after = door;
+            before[axis] -= back;
+            after[axis] += forward;
+            let waypoints = vec![from_coords(before), from_coords(door), from_coords(after)];
+            let blocked = walls.iter().any(|wall| {
+                waypoints
+                    .iter()
+                    .any(|&point| wall.collides(&AdditionalAction::MoveTo(point)))
+                    || wall
+                        .sweep(waypoints[0], waypoints[2] - waypoints[0])
+                        .is_some()
+            });
+            if blocked {
+                None
+            } else {
+                Some(waypoints)
+            }
+        };
+        Pathfinder::find_door(first, last, through)
+    }
+
+    /// The part of the face between `low` and `high`, flat along `axis`, where doors are
+    /// tried - the face without a `CLEARANCE` wide margin, or just its middle along the axes
+    /// where it is too narrow for that.
+    fn door_area(low: [f64; 4], high: [f64; 4], axis: usize) -> ([f64; 4], [f64; 4]) {
+        let mut first = low;
+        let mut last = high;
+        for i in (0..4).filter(|&i| i != axis) {
+            if high[i] - low[i] > 2.0 * CLEARANCE {
+                first[i] += CLEARANCE;
+                last[i] -= CLEARANCE;
+            } else {
+                first[i] = (low[i] + high[i]) / 2.0;
+                last[i] = first[i];
+            }
+        }
+        (first, last)
+    }
+
+    /// Finds a door in the door area between the corners `first` and `last` that `through`
+    /// lets the player pass, coarse to fine. The first grid of doors has the middle, the corners
+    /// and the midpoints of the edges of the area, and every next grid halves the spacing of the
+    /// previous one along the axes where it is still wider than `DOOR_SPACING`. The new doors
+    /// of every grid are tried nearest to the middle first.
+    ///
+    /// The search gives up on a grid with more than `MAX_DOORS` doors, so a narrow way through
+    /// a huge face can be missed.
+    fn find_door<T, F>(first: [f64; 4], last: [f64; 4], through: F) -> Option<T>
+    where
+        F: Fn([f64; 4]) -> Option<T>,
+    {
+        let middle = middle(first, last);
+        let distance = |door: &[f64; 4]| {
+            (0..4)
+                .map(|i| (door[i] - middle[i]) * (door[i] - middle[i]))
+                .sum::<f64>()
+        };
+        // the number of intervals of the grid along every axis; a flat axis has a single value
+        let mut intervals = [0; 4];
+        for i in 0..4 {
+            if last[i] > first[i] {
+                intervals[i] = 2;
+            }
+        }
+        // the axes along which the grid is finer than the previous one
+        let mut refined: Option<[bool; 4]> = None;
+
+        loop {
+            let size: usize = intervals.iter().map(|n| n + 1).product();
+            if size > MAX_DOORS {
+                return None;
+            }
+            let mut doors = Vec::new();
+            for k in 0..size {
+                let mut index = [0; 4];
+                let mut rest = k;
+                for i in 0..4 {
+                    index[i] = rest % (intervals[i] + 1);
+                    rest /= intervals[i] + 1;
+                }
+                // the doors at even indices along the refined axes were in the previous grid
+                if let Some(refined) = refined {
+                    if (0..4).all(|i| !refined[i] || index[i] % 2 == 0) {
+                        continue;
+                    }
+                }
+                let mut door = first;
+                for i in (0..4).filter(|&i| intervals[i] > 0) {
+                    door[i] += (last[i] - first[i]) * index[i] as f64 / intervals[i] as f64;
+                }
+                doors.push(door);
+            }
+            doors.sort_by(|a, b| {
+                distance(a)
+                    .partial_cmp(&distance(b))
+                    .unwrap_or(Ordering::Equal)
+            });
+            if let Some(found) = doors.into_iter().find_map(&through) {
+                return Some(found);
+            }
+
+            let mut finer = [false; 4];
+            for i in 0..4 {
+                finer[i] = intervals[i] > 0
+                    && (last[i] - first[i]) / intervals[i] as f64 > DOOR_SPACING + EPSILON;
+                if finer[i] {
+                    intervals[i] *= 2;
+                }
+            }
+            if !finer.iter().any(|&f| f) {
+                return None;
+            }
+            refined = Some(finer);
+        }
+    }
+
+    fn contains(&self, cell: usize, point: Vector) -> bool {
+        let p = coords(point);
+        let (min, max) = self.boxes[cell];
+        (0..4).all(|i| p[i] >= min[i] - EPSILON && p[i] <= max[i] + EPSILON)
+    }
+
+    /// Finds a route from `start` to the target. The result begins at `start`, ends at the
+    /// middle of the target, and every two consecutive waypoints lie in the same cell, so the
+    /// player can go straight from one to the next.
+    pub fn find_path(&self, start: Vector) -> Result<Vec<Vector>, PathError> {
+        let blocked = self
+            .level
+            .walls()
+            .iter()
+            .any(|wall| wall.collides(&AdditionalAction::MoveTo(start)));
+        if blocked {
+            return Err(PathError::StartBlocked);
+        }
+
+        // breadth-first search from every cell containing the start
+        let mut previous: Vec<Option<(usize, usize)>> = vec![None; self.boxes.len()];
+        let mut visited = vec![false; self.boxes.len()];
+        let mut queue = VecDeque::new();
+        for cell in 0..self.boxes.len() {
+            if self.contains(cell, start) {
+                visited[cell] = true;
+                queue.push_back(cell);
+            }
+        }
+        if queue.is_empty() {
+            return Err(PathError::StartOutside);
+        }
+
+        while let Some(cell) = queue.pop_front() {
+            if *self.level.cells()[cell].contents() == CellContents::Target {
+                return Ok(self.collect_path(&previous, cell, start));
+            }
+            for (index, passage) in self.passages[cell].iter().enumerate() {
+                if !visited[passage.to] {
+                    visited[passage.to] = true;
+                    previous[passage.to] = Some((cell, index));
+                    queue.push_back(passage.to);
+                }
+            }
+        }
+
+        Err(PathError::Unreachable)
+    }
+
+    fn collect_path(
+        &self,
+        previous: &[Option<(usize, usize)>],
+        last: usize,
+        start: Vector,
+    ) -> Vec<Vector> {
+        let mut steps = Vec::new();
+        let mut cell = last;
+        while let Some((from, index)) = previous[cell] {
+            steps.push(&self.passages[from][index]);
+            cell = from;
+        }
+        steps.reverse();
+
+        let mut path = vec![start];
+        for passage in steps {
+            path.extend(passage.waypoints.iter().cloned());
+        }
+        path.push(self.level.target().position());
+        path
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::generator::Maze;
+    use super::super::levels::Level;
+    use super::{PathError, Pathfinder, MAX_DOORS};
+    use graph4d::geometry::Vector;
+    use std::cell::Cell;
+
+    fn solve(text: &str) -> Result<Vec<Vector>, PathError> {
+        let mut level: Level = text.parse().unwrap();
+        let start = level.player().position();
+        Pathfinder::new(&level).find_path(start)
+    }
+
+    #[test]
+    fn test_shipped_levels_solvable() {
+        assert!(solve(include_str!("../level1.dat")).is_ok());
+        assert!(solve(include_str!("../level2.dat")).is_ok());
+    }
+
+    #[test]
+    fn test_generated_levels_solvable() {
+        for seed in 0..3 {
+            let mut level = Level::from_cells(Maze::generate(3, seed).cells()).unwrap();
+            let start = level.player().position();
+            assert!(Pathfinder::new(&level).find_path(start).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_simple_path() {
+        let path = solve(
+            "-2.5 -2.5 -2.5 -2.5\n2.5 2.5 2.5 2.5\nxXyYzwW\n\
+             -2.5 -2.5 2.5 -2.5\n2.5 2.5 7.5 2.5\nT\n",
+        )
+        .unwrap();
+        let expected = [
+            Vector::new(0.0, 0.0, 0.0, 0.0),
+            Vector::new(0.0, 0.0, 2.0, 0.0),
+            Vector::new(0.0, 0.0, 2.5, 0.0),
+            Vector::new(0.0, 0.0, 3.0, 0.0),
+            Vector::new(0.0, 0.0, 5.0, 0.0),
+        ];
+        assert_eq!(path.len(), expected.len());
+        assert!(path.iter().zip(expected.iter()).all(|(a, b)| a == b));
+    }
+
+    #[test]
+    fn test_door_off_the_middle() {
+        // a wall across most of the face between the cells, leaving a gap at high x
+        let path = solve(
+            "wall -1 0 2.5 0 3 5 5 zw 90\n\
+             -2.5 -2.5 -2.5 -2.5\n2.5 2.5 2.5 2.5\nxXyYzwW\n\
+             -2.5 -2.5 2.5 -2.5\n2.5 2.5 7.5 2.5\nT\n",
+        )
+        .unwrap();
+        assert_eq!(path.len(), 5);
+        let door = path[2];
+        assert!((door.z() - 2.5).abs() < 1e-9);
+        assert!(door.x() > 0.7);
+
+        // a gap too narrow for the player
+        let result = solve(
+            "wall -0.2 0 2.5 0 4.6 5 5 zw 90\n\
+             -2.5 -2.5 -2.5 -2.5\n2.5 2.5 2.5 2.5\nxXyYzwW\n\
+             -2.5 -2.5 2.5 -2.5\n2.5 2.5 7.5 2.5\nT\n",
+        );
+        assert_eq!(result.err(), Some(PathError::Unreachable));
+    }
+
+    #[test]
+    fn test_door_search() {
+        let tried = Cell::new(0);
+        let nowhere = |_| {
+            tried.set(tried.get() + 1);
+            None::<()>
+        };
+
+        // a usual face gets every door of the finest grid tried once
+        let first = [-2.0, -2.0, 2.5, -2.0];
+        let last = [2.0, 2.0, 2.5, 2.0];
+        assert_eq!(Pathfinder::find_door(first, last, &nowhere), None);
+        assert_eq!(tried.get(), 17 * 17 * 17);
+
+        // a huge one gets a coarser grid
+        tried.set(0);
+        let first = [-19.5, -19.5, 20.0, -19.5];
+        let last = [19.5, 19.5, 20.0, 19.5];
+        assert_eq!(Pathfinder::find_door(first, last, &nowhere), None);
+        assert!(tried.get() <= MAX_DOORS);
+
+        // the middle is tried first
+        let middle = Pathfinder::find_door(first, last, |door| Some(door)).unwrap();
+        assert_eq!(middle, [0.0, 0.0, 20.0, 0.0]);
+    }
+
+    #[test]
+    fn test_walled_off_target() {
+        let result = solve(
+            "-2.5 -2.5 -2.5 -2.5\n2.5 2.5 2.5 2.5\nxXyYzZwW\n\
+             -2.5 -2.5 2.5 -2.5\n2.5 2.5 7.5 2.5\nT\n",
+        );
+        assert_eq!(result.err(), Some(PathError::Unreachable));
+    }
+
+    #[test]
+    fn test_start_outside() {
+        let result = solve(
+            "2.5 2.5 2.5 2.5\n7.5 7.5 7.5 7.5\nxXyYzwW\n\
+             2.5 2.5 7.5 2.5\n7.5 7.5 12.5 7.5\nT\n",
+        );
+        assert_eq!(result.err(), Some(PathError::StartOutside));
+    }
+}
@@ -0,0 +1,434 @@
+use super::input::ActionState;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+const HEADER: &str = "4d-labyrinth replay";
+
+/// Everything that can go wrong while reading a replay.
+///
+/// Line numbers are 1-based.
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    BadHeader,
+    BadStep { line: usize },
+    BadOrigin { line: usize, text: String },
+    BadTick { line: usize, text: String },
+}
+
+impl Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReplayError::Io(ref err) => write!(f, "I/O error: {}", err),
+            ReplayError::BadHeader => write!(f, "not a replay file"),
+            ReplayError::BadStep { line } => {
+                write!(f, "line {}: expected `step SECONDS`", line)
+            }
+            ReplayError::BadOrigin { line, ref text } => write!(
+                f,
+                "line {}: expected `maze SIZE SEED` or `level FINGERPRINT TITLE`, found `{}`",
+                line, text
+            ),
+            ReplayError::BadTick { line, ref text } => write!(f, "line {}: {}", line, text),
+        }
+    }
+}
+
+impl Error for ReplayError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ReplayError::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ReplayError {
+    fn from(err: io::Error) -> ReplayError {
+        ReplayError::Io(err)
+    }
+}
+
+/// What a replay was recorded on, so that it is only played back on the same levels.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReplayOrigin {
+    /// The title and the fingerprint (see `Level::fingerprint`) of every level, in the order
+    /// they are played.
+    pub levels: Vec<(String, u64)>,
+    /// The size and the seed of the maze, if the level was generated.
+    pub maze: Option<(usize, u64)>,
+}
+
+impl ReplayOrigin {
+    /// Checks that a replay recorded on `self` can be played back on `played`.
+    pub fn check(&self, played: &ReplayOrigin) -> Result<(), String> {
+        if self.maze != played.maze {
+            return Err(match self.maze {
+                Some((size, seed)) => format!(
+                    "recorded on the maze from `--generate {} --seed {}`",
+                    size, seed
+                ),
+                None => "recorded on a level pack, not on a generated maze".to_owned(),
+            });
+        }
+        let pairs = self.levels.iter().zip(played.levels.iter());
+        for (number, (recorded, level)) in (1..).zip(pairs) {
+            if recorded.0 != level.0 {
+                return Err(format!(
+                    "level {} of the replay is `{}`, not `{}`",
+                    number, recorded.0, level.0
+                ));
+            }
+            if recorded.1 != level.1 {
+                return Err(format!(
+                    "level {} (`{}`) has changed since the replay was recorded",
+                    number, recorded.0
+                ));
+            }
+        }
+        if self.levels.len() != played.levels.len() {
+            return Err(format!(
+                "recorded on {} levels instead of {}",
+                self.levels.len(),
+                played.levels.len()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Collects the actions of every step of the simulation, to be saved as a replay.
+///
+/// A replay file looks like this:
+///
+/// ```text
+/// 4d-labyrinth replay
+/// step 0.008333333333333333
+/// maze 3 42
+/// level 5f7b3a1c9e2d4068 Random maze 3x3x3x3
+/// 120 move_forward
+/// 1
+/// 35 move_forward rotate_xw+ turn:rotate_yw=0.0026179938779914945
+/// ```
+///
+/// The length of a step in seconds is followed by the origin of the replay: the size and seed of
+/// the maze, only if it was generated, and the fingerprint and title of every level. After that,
+/// every line gives the number of consecutive steps with the same actions, followed by the
+/// actions as written by `ActionState`.
+pub struct Recorder {
+    step: f64,
+    origin: ReplayOrigin,
+    ticks: Vec<(usize, String)>,
+}
+
+impl Recorder {
+    pub fn new(step: f64, origin: ReplayOrigin) -> Recorder {
+        Recorder {
+            step: step,
+            origin: origin,
+            ticks: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, actions: &ActionState) {
+        let text = actions.to_string();
+        match self.ticks.last_mut() {
+            Some(&mut (ref mut count, ref last)) if *last == text => *count += 1,
+            _ => self.ticks.push((1, text)),
+        }
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "{}", HEADER)?;
+        writeln!(writer, "step {}", self.step)?;
+        if let Some((size, seed)) = self.origin.maze {
+            writeln!(writer, "maze {} {}", size, seed)?;
+        }
+        for &(ref title, fingerprint) in self.origin.levels.iter() {
+            writeln!(writer, "level {:016x} {}", fingerprint, title)?;
+        }
+        for &(count, ref text) in self.ticks.iter() {
+            if text.is_empty() {
+                writeln!(writer, "{}", count)?;
+            } else {
+                writeln!(writer, "{} {}", count, text)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A recorded run, played back step by step.
+pub struct Replay {
+    step: f64,
+    origin: ReplayOrigin,
+    ticks: Vec<(usize, ActionState)>,
+    // the position in `ticks` and the steps already taken from it
+    next: usize,
+    taken: usize,
+}
+
+impl Replay {
+    pub fn from_file<P: AsRef<Path>>(file_name: P) -> Result<Replay, ReplayError> {
+        let f = File::open(file_name)?;
+        Replay::from_reader(BufReader::new(f))
+    }
+
+    pub fn from_reader<R: BufRead>(reader: R) -> Result<Replay, ReplayError> {
+        let mut lines = reader.lines();
+        match lines.next() {
+            Some(line) => {
+                if line?.trim() != HEADER {
+                    return Err(ReplayError::BadHeader);
+                }
+            }
+            None => return Err(ReplayError::BadHeader),
+        }
+        let step = match lines.next() {
+            Some(line) => {
+                let line = line?;
+                let mut words = line.split_whitespace();
+                match (words.next(), words.next().map(str::parse), words.next()) {
+                    (Some("step"), Some(Ok(step)), None) if step > 0.0 => step,
+                    _ => return Err(ReplayError::BadStep { line: 2 }),
+                }
+            }
+            None => return Err(ReplayError::BadStep { line: 2 }),
+        };
+
+        let mut origin = ReplayOrigin::default();
+        let mut ticks = Vec::new();
+        for (index, line) in lines.enumerate() {
+            let line_num = index + 3;
+            let line = line?;
+            let text = line.trim();
+            if text.is_empty() {
+                continue;
+            }
+            // the origin comes before the first tick
+            let keyword = text.split_whitespace().next().unwrap();
+            if ticks.is_empty() && (keyword == "maze" || keyword == "level") {
+                let bad_origin = || ReplayError::BadOrigin {
+                    line: line_num,
+                    text: text.to_owned(),
+                };
+                let mut words = text[keyword.len()..].trim_start().splitn(2, ' ');
+                let (first, rest) = (words.next().unwrap_or(""), words.next().unwrap_or(""));
+                if keyword == "maze" && origin.maze.is_none() && origin.levels.is_empty() {
+                    match (first.parse(), rest.trim().parse()) {
+                        (Ok(size), Ok(seed)) => origin.maze = Some((size, seed)),
+                        _ => return Err(bad_origin()),
+                    }
+                } else if keyword == "level" && !rest.trim().is_empty() {
+                    let fingerprint = u64::from_str_radix(first, 16).map_err(|_| bad_origin())?;
+                    origin.levels.push((rest.trim().to_owned(), fingerprint));
+                } else {
+                    return Err(bad_origin());
+                }
+                continue;
+            }
+            let bad_tick = |text: String| ReplayError::BadTick {
+                line: line_num,
+                text: text,
+            };
+            let (count, actions) = match text.find(' ') {
+                Some(pos) => (&text[..pos], &text[pos + 1..]),
+                None => (text, ""),
+            };
+            let count = match count.parse() {
+                Ok(count) if count > 0 => count,
+                _ => return Err(bad_tick(format!("invalid number of steps `{}`", count))),
+            };
+            ticks.push((count, actions.parse().map_err(bad_tick)?));
+        }
+        Ok(Replay {
+            step: step,
+            origin: origin,
+            ticks: ticks,
+            next: 0,
+            taken: 0,
+        })
+    }
+
+    /// The length of a step of the recorded simulation, in seconds.
+    pub fn step(&self) -> f64 {
+        self.step
+    }
+
+    /// What the replay was recorded on.
+    pub fn origin(&self) -> &ReplayOrigin {
+        &self.origin
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next == self.ticks.len()
+    }
+
+    /// The actions of the next step, or `None` at the end of the replay.
+    pub fn next_actions(&mut self) -> Option<ActionState> {
+        let (count, ref actions) = *self.ticks.get(self.next)?;
+        self.taken += 1;
+        if self.taken == count {
+            self.next += 1;
+            self.taken = 0;
+        }
+        Some(actions.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::bot::Bot;
+    use super::super::game::{Game, Stage};
+    use super::super::input::{Action, ActionState, Axis};
+    use super::super::pack::Pack;
+    use super::super::simulation::TIME_STEP;
+    use super::{Recorder, Replay, ReplayError, ReplayOrigin};
+
+    fn level1() -> Game {
+        let pack = Pack::builtin();
+        Game::new(vec![Stage::load(&pack.entries()[0]).unwrap()])
+    }
+
+    #[test]
+    fn test_file_format() {
+        let origin = ReplayOrigin {
+            levels: vec![("Random maze 3x3x3x3".to_owned(), 0x0123_4567_89ab_cdef)],
+            maze: Some((3, 42)),
+        };
+        let mut recorder = Recorder::new(0.01, origin.clone());
+        let mut actions = ActionState::new();
+        for _ in 0..3 {
+            recorder.record(&actions);
+        }
+        actions.set(Action::MoveForward, true);
+        actions.set(Action::RotateXWPlus, true);
+        actions.set_axis(Axis::MoveRight, 0.1 + 0.2);
+        actions.turn(Axis::RotateYW, -1e-17);
+        recorder.record(&actions);
+        recorder.record(&ActionState::new());
+
+        let mut file = Vec::new();
+        recorder.write_to(&mut file).unwrap();
+        assert_eq!(
+            String::from_utf8(file.clone()).unwrap(),
+            "4d-labyrinth replay\n\
+             step 0.01\n\
+             maze 3 42\n\
+             level 0123456789abcdef Random maze 3x3x3x3\n\
+             3\n\
+             1 move_forward rotate_xw+ axis:move_right=0.30000000000000004 \
+             turn:rotate_yw=-0.00000000000000001\n\
+             1\n"
+        );
+
+        let mut replay = Replay::from_reader(&file[..]).unwrap();
+        assert_eq!(replay.step(), 0.01);
+        assert_eq!(replay.origin(), &origin);
+        for _ in 0..3 {
+            assert_eq!(replay.next_actions().unwrap().to_string(), "");
+        }
+        let read = replay.next_actions().unwrap();
+        assert_eq!(read.axis(Axis::MoveRight), 0.1 + 0.2);
+        assert_eq!(read.turned(Axis::RotateYW), -1e-17);
+        assert!(read.is_active(Action::RotateXWPlus));
+        assert!(replay.next_actions().is_some());
+        assert!(replay.next_actions().is_none());
+    }
+
+    #[test]
+    fn test_errors() {
+        let parse = |text: &str| Replay::from_reader(text.as_bytes()).err();
+        match parse("step 0.01\n") {
+            Some(ReplayError::BadHeader) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match parse("4d-labyrinth replay\nstep -1\n") {
+            Some(ReplayError::BadStep { line: 2 }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match parse("4d-labyrinth replay\nstep 0.01\n2 move_forward\n0\n") {
+            Some(ReplayError::BadTick { line: 4, .. }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match parse("4d-labyrinth replay\nstep 0.01\n2 jump\n") {
+            Some(ReplayError::BadTick { line: 3, .. }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match parse("4d-labyrinth replay\nstep 0.01\nlevel xyz First steps\n") {
+            Some(ReplayError::BadOrigin { line: 3, .. }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match parse("4d-labyrinth replay\nstep 0.01\nmaze 3\n") {
+            Some(ReplayError::BadOrigin { line: 3, .. }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_replay_solution() {
+        // the bot solves the level while it is being recorded
+        let mut game = level1();
+        let origin = ReplayOrigin {
+            levels: game.levels(),
+            maze: None,
+        };
+        game.start_recording(origin);
+        let mut bot = Bot::new(game.level_mut().unwrap()).unwrap();
+        let mut result = None;
+        while result.is_none() {
+            result = game.update_with(TIME_STEP, |player| bot.actions(player));
+        }
+        let result = result.unwrap();
+        let mut file = Vec::new();
+        game.recording().unwrap().write_to(&mut file).unwrap();
+
+        // replaying it reaches the target at the very same step
+        let mut replay = Replay::from_reader(&file[..]).unwrap();
+        assert_eq!(replay.step(), TIME_STEP);
+        let mut game = level1();
+        let origin = ReplayOrigin {
+            levels: game.levels(),
+            maze: None,
+        };
+        assert!(replay.origin().check(&origin).is_ok());
+        let mut replayed = None;
+        let mut steps = 0;
+        while let Some(actions) = replay.next_actions() {
+            assert!(replayed.is_none(), "the target was reached too early");
+            replayed = game.update_with(TIME_STEP, |_| actions.clone());
+            steps += 1;
+        }
+        assert_eq!(replayed, Some(result.clone()));
+        assert_eq!(steps as f64 * TIME_STEP, result.time);
+        assert!(game.is_finished());
+    }
+
+    #[test]
+    fn test_origin_mismatch() {
+        let recorded = ReplayOrigin {
+            levels: vec![("First steps".to_owned(), 1), ("Second".to_owned(), 2)],
+            maze: None,
+        };
+        let mut played = recorded.clone();
+        assert!(recorded.check(&played).is_ok());
+
+        // an edited level
+        played.levels[1].1 = 3;
+        assert!(recorded.check(&played).is_err());
+        // another pack
+        played.levels = vec![("First steps".to_owned(), 1)];
+        assert!(recorded.check(&played).is_err());
+        // a generated maze
+        let maze = |seed| ReplayOrigin {
+            levels: vec![("Random maze 3x3x3x3".to_owned(), seed)],
+            maze: Some((3, seed)),
+        };
+        assert!(recorded.check(&maze(1)).is_err());
+        assert!(maze(1).check(&maze(2)).is_err());
+        assert!(maze(2).check(&maze(2)).is_ok());
+    }
+}
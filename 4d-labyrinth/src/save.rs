@@ -0,0 +1,289 @@
+use super::objects::Pose;
+use graph4d::geometry::{Matrix, Vector};
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+const HEADER: &str = "4d-labyrinth save";
+
+const KEYS: [&str; 10] = [
+    "level",
+    "title",
+    "fingerprint",
+    "time",
+    "position",
+    "right",
+    "up",
+    "front",
+    "ana",
+    "orientation",
+];
+
+/// Everything that can go wrong while reading a saved game.
+///
+/// Line numbers are 1-based.
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    BadHeader,
+    BadLine { line: usize, text: String },
+    DuplicateKey { line: usize, key: String },
+    Missing(&'static str),
+}
+
+impl Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SaveError::Io(ref err) => write!(f, "I/O error: {}", err),
+            SaveError::BadHeader => write!(f, "not a saved game"),
+            SaveError::BadLine { line, ref text } => {
+                write!(f, "line {}: invalid line `{}`", line, text)
+            }
+            SaveError::DuplicateKey { line, ref key } => {
+                write!(f, "line {}: `{}` is given more than once", line, key)
+            }
+            SaveError::Missing(key) => write!(f, "`{}` is missing", key),
+        }
+    }
+}
+
+impl Error for SaveError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            SaveError::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SaveError {
+    fn from(err: io::Error) -> SaveError {
+        SaveError::Io(err)
+    }
+}
+
+/// The progress of a game that was closed before the end, stored like this:
+///
+/// ```text
+/// 4d-labyrinth save
+/// level 2
+/// title Into the fourth dimension
+/// fingerprint 5f7b3a1c9e2d4068
+/// time 12.5
+/// position 1 0.5 7.25 0
+/// right 1 0 0 0
+/// up 0 1 0 0
+/// front 0 0 1 0
+/// ana 0 0 0 1
+/// orientation 1 0 0 0 0 1 0 0 0 0 1 0 0 0 0 1
+/// ```
+///
+/// The title and the fingerprint of the level (see `Level::fingerprint`) are there to check
+/// that the game is continued in the same level. The orientation is the rotation part of the
+/// matrix, row by row. All the numbers are written so that reading them back gives exactly the
+/// same values.
+pub struct SavedGame {
+    /// The number of the level, starting from 1.
+    pub level: usize,
+    pub title: String,
+    pub fingerprint: u64,
+    /// Simulated time since the start of the level, in seconds.
+    pub time: f64,
+    pub pose: Pose,
+}
+
+impl SavedGame {
+    pub fn from_file<P: AsRef<Path>>(file_name: P) -> Result<SavedGame, SaveError> {
+        let f = File::open(file_name)?;
+        SavedGame::from_reader(BufReader::new(f))
+    }
+
+    pub fn from_reader<R: BufRead>(reader: R) -> Result<SavedGame, SaveError> {
+        let mut lines = reader.lines();
+        match lines.next() {
+            Some(line) => {
+                if line?.trim() != HEADER {
+                    return Err(SaveError::BadHeader);
+                }
+            }
+            None => return Err(SaveError::BadHeader),
+        }
+
+        let mut level = None;
+        let mut title = None;
+        let mut fingerprint = None;
+        let mut time = None;
+        let mut vectors = [None; 5];
+        let mut orientation = None;
+        let mut seen = [false; 10];
+        for (index, line) in lines.enumerate() {
+            let line_num = index + 2;
+            let line = line?;
+            let text = line.trim();
+            if text.is_empty() {
+                continue;
+            }
+            let bad_line = || SaveError::BadLine {
+                line: line_num,
+                text: text.to_owned(),
+            };
+            let (key, value) = match text.find(' ') {
+                Some(pos) => (&text[..pos], text[pos + 1..].trim()),
+                None => (text, ""),
+            };
+            let key_index = KEYS.iter().position(|&k| k == key).ok_or_else(bad_line)?;
+            if seen[key_index] {
+                return Err(SaveError::DuplicateKey {
+                    line: line_num,
+                    key: key.to_owned(),
+                });
+            }
+            seen[key_index] = true;
+            match key_index {
+                0 => match value.parse() {
+                    Ok(number) if number > 0 => level = Some(number),
+                    _ => return Err(bad_line()),
+                },
+                1 => title = Some(value.to_owned()),
+                2 => match u64::from_str_radix(value, 16) {
+                    Ok(hash) => fingerprint = Some(hash),
+                    _ => return Err(bad_line()),
+                },
+                3 => match value.parse() {
+                    Ok(seconds) if seconds >= 0.0 => time = Some(seconds),
+                    _ => return Err(bad_line()),
+                },
+                9 => {
+                    let numbers = parse_numbers(value, 16).ok_or_else(bad_line)?;
+                    let mut matrix = *Matrix::identity().as_array();
+                    for (i, row) in matrix.iter_mut().enumerate().take(4) {
+                        row[..4].copy_from_slice(&numbers[i * 4..i * 4 + 4]);
+                    }
+                    orientation = Some(Matrix::from_array(matrix));
+                }
+                _ => {
+                    let coords = parse_numbers(value, 4).ok_or_else(bad_line)?;
+                    vectors[key_index - 4] =
+                        Some(Vector::new(coords[0], coords[1], coords[2], coords[3]));
+                }
+            }
+        }
+
+        let vector = |i: usize| vectors[i].ok_or(SaveError::Missing(KEYS[i + 4]));
+        Ok(SavedGame {
+            level: level.ok_or(SaveError::Missing("level"))?,
+            title: title.ok_or(SaveError::Missing("title"))?,
+            fingerprint: fingerprint.ok_or(SaveError::Missing("fingerprint"))?,
+            time: time.ok_or(SaveError::Missing("time"))?,
+            pose: Pose {
+                position: vector(0)?,
+                right: vector(1)?,
+                up: vector(2)?,
+                front: vector(3)?,
+                ana: vector(4)?,
+                orientation: orientation.ok_or(SaveError::Missing("orientation"))?,
+            },
+        })
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "{}", HEADER)?;
+        writeln!(writer, "level {}", self.level)?;
+        writeln!(writer, "title {}", self.title)?;
+        writeln!(writer, "fingerprint {:016x}", self.fingerprint)?;
+        writeln!(writer, "time {}", self.time)?;
+        let pose = &self.pose;
+        let vectors = [pose.position, pose.right, pose.up, pose.front, pose.ana];
+        for (key, vector) in KEYS[4..9].iter().zip(vectors.iter()) {
+            let coords: Vec<_> = (0..4).map(|i| vector.coord(i).to_string()).collect();
+            writeln!(writer, "{} {}", key, coords.join(" "))?;
+        }
+        let orientation = pose.orientation.as_array();
+        let numbers: Vec<_> = orientation[..4]
+            .iter()
+            .flat_map(|row| row[..4].iter().map(|x| x.to_string()))
+            .collect();
+        writeln!(writer, "orientation {}", numbers.join(" "))
+    }
+}
+
+/// Parses exactly `count` numbers separated by whitespace.
+fn parse_numbers(text: &str, count: usize) -> Option<Vec<f64>> {
+    let numbers = text
+        .split_whitespace()
+        .map(|word| word.parse().ok())
+        .collect::<Option<Vec<f64>>>()?;
+    if numbers.len() == count {
+        Some(numbers)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::objects::Player;
+    use super::{SaveError, SavedGame};
+    use graph4d::geometry::Vector;
+
+    #[test]
+    fn test_round_trip() {
+        let mut player = Player::new();
+        player.go(Vector::new(0.1, 0.2, 1.0 / 3.0, -2.5));
+        player.rotate_yw(0.3);
+        player.rotate_xw(-1.1);
+        player.rotate_zw(0.7);
+        let saved = SavedGame {
+            level: 2,
+            title: "Into the fourth dimension".to_owned(),
+            fingerprint: 0x0123_4567_89ab_cdef,
+            time: 12.5,
+            pose: player.pose(),
+        };
+        let mut file = Vec::new();
+        saved.write_to(&mut file).unwrap();
+
+        let read = SavedGame::from_reader(&file[..]).unwrap();
+        assert_eq!(read.level, 2);
+        assert_eq!(read.title, "Into the fourth dimension");
+        assert_eq!(read.fingerprint, 0x0123_4567_89ab_cdef);
+        assert_eq!(read.time, 12.5);
+        let (expected, pose) = (saved.pose, read.pose);
+        let pairs = [
+            (expected.position, pose.position),
+            (expected.right, pose.right),
+            (expected.up, pose.up),
+            (expected.front, pose.front),
+            (expected.ana, pose.ana),
+        ];
+        for &(expected, vector) in pairs.iter() {
+            for i in 0..5 {
+                assert_eq!(vector.coord(i), expected.coord(i));
+            }
+        }
+        assert_eq!(pose.orientation.as_array(), expected.orientation.as_array());
+    }
+
+    #[test]
+    fn test_errors() {
+        let parse = |text: &str| SavedGame::from_reader(text.as_bytes()).err();
+        match parse("level 1\n") {
+            Some(SaveError::BadHeader) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match parse("4d-labyrinth save\nlevel 1\nposition 1 2 3\n") {
+            Some(SaveError::BadLine { line: 3, .. }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match parse("4d-labyrinth save\nlevel 1\nlevel 2\n") {
+            Some(SaveError::DuplicateKey { line: 3, .. }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match parse("4d-labyrinth save\nlevel 1\ntitle First steps\nfingerprint 1f\ntime 3\n") {
+            Some(SaveError::Missing("position")) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
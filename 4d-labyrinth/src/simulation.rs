@@ -0,0 +1,220 @@
+use super::input::ActionState;
+use super::levels::Level;
+use super::objects::Player;
+use super::replay::Recorder;
+
+/// The length of a single step of the simulation, in seconds.
+pub const TIME_STEP: f64 = 1.0 / 120.0;
+/// The longest time simulated after a single frame. After a longer frame (like when the window
+/// was being dragged) the game slows down instead of making the player jump.
+pub const MAX_FRAME_TIME: f64 = 0.25;
+
+/// Turns the varying time between frames into a whole number of fixed steps, carrying the
+/// remainder over to the next frame.
+pub struct FixedTimestep {
+    step: f64,
+    accumulator: f64,
+}
+
+impl FixedTimestep {
+    pub fn new(step: f64) -> FixedTimestep {
+        FixedTimestep {
+            step: step,
+            accumulator: 0.0,
+        }
+    }
+
+    pub fn step(&self) -> f64 {
+        self.step
+    }
+
+    /// Adds the time of a frame and returns the number of steps to simulate.
+    pub fn advance(&mut self, frame_time: f64) -> usize {
+        self.accumulator += frame_time.max(0.0).min(MAX_FRAME_TIME);
+        let steps = (self.accumulator / self.step).floor();
+        self.accumulator -= steps * self.step;
+        steps as usize
+    }
+}
+
+/// The state of a level being played, advanced in fixed steps independently of rendering, so
+/// that the same input always gives the same result.
+pub struct Simulation {
+    level: Level,
+    clock: FixedTimestep,
+    steps: u64,
+    completed: bool,
+    // turns from frames too short for a whole step
+    deferred: ActionState,
+    recorder: Option<Recorder>,
+}
+
+impl Simulation {
+    pub fn new(level: Level) -> Simulation {
+        Simulation {
+            level: level,
+            clock: FixedTimestep::new(TIME_STEP),
+            steps: 0,
+            completed: false,
+            deferred: ActionState::new(),
+            recorder: None,
+        }
+    }
+
+    pub fn level_mut(&mut self) -> &mut Level {
+        &mut self.level
+    }
+
+    /// The simulated time since the start of the level, in seconds.
+    pub fn elapsed(&self) -> f64 {
+        self.steps as f64 * self.clock.step()
+    }
+
+    /// Sets the simulated time, rounded to whole steps, as when continuing a saved game.
+    pub fn set_elapsed(&mut self, time: f64) {
+        self.steps = (time / self.clock.step()).round() as u64;
+    }
+
+    /// Whether the player has reached the target.
+    pub fn completed(&self) -> bool {
+        self.completed
+    }
+
+    /// Makes the simulation record the actions of every step from now on.
+    pub fn set_recorder(&mut self, recorder: Option<Recorder>) {
+        self.recorder = recorder;
+    }
+
+    pub fn recorder(&self) -> Option<&Recorder> {
+        self.recorder.as_ref()
+    }
+
+    pub fn take_recorder(&mut self) -> Option<Recorder> {
+        self.recorder.take()
+    }
+
+    /// Simulates the time of a frame with the given input and returns the number of steps
+    /// taken.
+    ///
+    /// Mouse turns in `actions` are applied in the first step only. If the frame was too short
+    /// for a whole step, they are kept for the next one.
+    pub fn update(&mut self, frame_time: f64, actions: &ActionState) -> usize {
+        let steps = self.clock.advance(frame_time);
+        if steps == 0 {
+            self.deferred.add_turns(actions);
+            return 0;
+        }
+        let mut first = actions.clone();
+        first.add_turns(&self.deferred);
+        self.deferred = ActionState::new();
+        self.step(&first);
+        let actions = actions.without_turns();
+        for _ in 1..steps {
+            self.step(&actions);
+        }
+        steps
+    }
+
+    /// Simulates the time of a frame, asking `controller` what the player does before every
+    /// step, and returns the number of steps taken.
+    ///
+    /// The controller isn't asked anymore once the level is completed, so that a replay can
+    /// continue in the next level.
+    pub fn update_with<F>(&mut self, frame_time: f64, mut controller: F) -> usize
+    where
+        F: FnMut(&Player) -> ActionState,
+    {
+        let steps = self.clock.advance(frame_time);
+        for _ in 0..steps {
+            if self.completed {
+                break;
+            }
+            let actions = controller(self.level.player());
+            self.step(&actions);
+        }
+        steps
+    }
+
+    /// Advances the simulation by a single step.
+    pub fn step(&mut self, actions: &ActionState) {
+        if self.completed {
+            return;
+        }
+        self.steps += 1;
+        if let Some(ref mut recorder) = self.recorder {
+            recorder.record(actions);
+        }
+        let step = self.clock.step();
+        let action = self.level.player().handle_input(actions, step);
+        self.completed = self.level.move_player(action);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::input::{Action, ActionState, Axis};
+    use super::super::pack::Pack;
+    use super::{FixedTimestep, Simulation, MAX_FRAME_TIME, TIME_STEP};
+
+    #[test]
+    fn test_fixed_timestep() {
+        let mut clock = FixedTimestep::new(0.01);
+        assert_eq!(clock.advance(0.004), 0);
+        assert_eq!(clock.advance(0.004), 0);
+        assert_eq!(clock.advance(0.004), 1);
+        assert_eq!(clock.advance(0.035), 3);
+        // long frames are cut short
+        assert_eq!(
+            clock.advance(10.0),
+            (MAX_FRAME_TIME / 0.01).round() as usize
+        );
+    }
+
+    /// Runs the first built-in level with the given frame times, adding up to a second.
+    fn run(frames: &[f64]) -> Simulation {
+        let level = Pack::builtin().entries()[0].load().unwrap();
+        let mut simulation = Simulation::new(level);
+        let mut actions = ActionState::new();
+        actions.set(Action::MoveForward, true);
+        actions.set(Action::RotateXYPlus, true);
+        for &frame in frames {
+            simulation.update(frame, &actions);
+        }
+        // so that rounding errors don't leave the last step out
+        simulation.update(TIME_STEP / 2.0, &actions);
+        simulation
+    }
+
+    #[test]
+    fn test_frame_rate_independence() {
+        let smooth = run(&[1.0 / 60.0; 60]);
+        let slow = run(&[0.1; 10]);
+        let uneven = run(&[0.3 / 120.0, 0.25, 0.2, 0.1, 0.2, 0.25 - 0.3 / 120.0]);
+        let mut results = [smooth, slow, uneven];
+        let elapsed = results[0].elapsed();
+        assert!((elapsed - 1.0).abs() < 1e-9);
+        let position = results[0].level_mut().player().position();
+        for other in results.iter_mut() {
+            assert_eq!(other.elapsed(), elapsed);
+            assert!(other.level_mut().player().position() == position);
+        }
+    }
+
+    #[test]
+    fn test_turns_applied_once() {
+        let level = Pack::builtin().entries()[0].load().unwrap();
+        let mut simulation = Simulation::new(level);
+        let mut actions = ActionState::new();
+        actions.turn(Axis::RotateYW, 0.25);
+        // too short for a step, then long enough for a few
+        assert_eq!(simulation.update(TIME_STEP / 2.0, &actions), 0);
+        assert_eq!(simulation.update(TIME_STEP * 3.0, &actions), 3);
+
+        let mut expected = Pack::builtin().entries()[0].load().unwrap();
+        expected.player().rotate_yw(0.5);
+        let axes = simulation.level_mut().player().axes();
+        for (axis, other) in axes.iter().zip(expected.player().axes().iter()) {
+            assert!(axis == other);
+        }
+    }
+}
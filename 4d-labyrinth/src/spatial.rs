@@ -0,0 +1,300 @@
+use graph4d::geometry::{Aabb, Hyperplane, Vector};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+type CellKey = [i64; 4];
+
+/// A uniform grid over the bounding boxes of level objects, answering which objects might be
+/// near a box or a hyperplane without looking at all of them.
+///
+/// Queries return indices into the slice of boxes the grid was built from. They can include
+/// objects that don't quite touch the query, but never miss one that does.
+pub struct SpatialIndex {
+    boxes: Vec<Aabb>,
+    cell_size: f64,
+    cells: HashMap<CellKey, Vec<usize>>,
+    // the range of occupied cells, so that huge queries don't walk empty space
+    min_cell: CellKey,
+    max_cell: CellKey,
+}
+
+impl SpatialIndex {
+    pub fn new(boxes: &[Aabb]) -> SpatialIndex {
+        // cells about the size of a typical object, so that each object falls into few cells
+        // and each cell holds few objects
+        let mut cell_size = 1.0;
+        if !boxes.is_empty() {
+            let total: f64 = boxes
+                .iter()
+                .map(|b| {
+                    (0..4)
+                        .map(|i| b.max().coord(i) - b.min().coord(i))
+                        .fold(0.0, f64::max)
+                })
+                .sum();
+            cell_size = (total / boxes.len() as f64).max(cell_size);
+        }
+
+        let mut index = SpatialIndex {
+            boxes: boxes.to_vec(),
+            cell_size: cell_size,
+            cells: HashMap::new(),
+            min_cell: [i64::max_value(); 4],
+            max_cell: [i64::min_value(); 4],
+        };
+        for (i, aabb) in boxes.iter().enumerate() {
+            let (low, high) = (index.cell_of(aabb.min()), index.cell_of(aabb.max()));
+            for axis in 0..4 {
+                index.min_cell[axis] = index.min_cell[axis].min(low[axis]);
+                index.max_cell[axis] = index.max_cell[axis].max(high[axis]);
+            }
+            for key in SpatialIndex::keys_between(low, high) {
+                index.cells.entry(key).or_insert_with(Vec::new).push(i);
+            }
+        }
+        index
+    }
+
+    fn cell_of(&self, point: Vector) -> CellKey {
+        let mut key = [0; 4];
+        for (axis, k) in key.iter_mut().enumerate() {
+            *k = (point.coord(axis) / self.cell_size).floor() as i64;
+        }
+        key
+    }
+
+    fn keys_between(low: CellKey, high: CellKey) -> Vec<CellKey> {
+        let mut keys = Vec::new();
+        for x in low[0]..high[0] + 1 {
+            for y in low[1]..high[1] + 1 {
+                for z in low[2]..high[2] + 1 {
+                    for w in low[3]..high[3] + 1 {
+                        keys.push([x, y, z, w]);
+                    }
+                }
+            }
+        }
+        keys
+    }
+
+    /// The objects from the given cells, without repetitions.
+    fn candidates<I: Iterator<Item = CellKey>>(&self, keys: I) -> Vec<usize> {
+        let mut result: Vec<usize> = keys
+            .filter_map(|key| self.cells.get(&key))
+            .flat_map(|items| items.iter().cloned())
+            .collect();
+        result.sort();
+        result.dedup();
+        result
+    }
+
+    /// The objects whose boxes overlap or touch `aabb`.
+    pub fn query_box(&self, aabb: &Aabb) -> Vec<usize> {
+        if self.cells.is_empty() {
+            return Vec::new();
+        }
+        let (mut low, mut high) = (self.cell_of(aabb.min()), self.cell_of(aabb.max()));
+        for axis in 0..4 {
+            low[axis] = low[axis].max(self.min_cell[axis]);
+            high[axis] = high[axis].min(self.max_cell[axis]);
+            if low[axis] > high[axis] {
+                return Vec::new();
+            }
+        }
+        let mut result = self.candidates(SpatialIndex::keys_between(low, high).into_iter());
+        result.retain(|&i| self.boxes[i].intersects(aabb));
+        result
+    }
+
+    /// The occupied cells crossed by `hyperplane`.
+    ///
+    /// The hyperplane is solved for the coordinate along which its normal is the longest. For
+    /// every column of cells along that axis, only the cells between the lowest and the highest
+    /// value the coordinate takes over the column are visited.
+    fn hyperplane_cells(&self, hyperplane: Hyperplane) -> Vec<CellKey> {
+        let mut keys = Vec::new();
+        if self.cells.is_empty() {
+            return keys;
+        }
+        let normal = hyperplane.normal();
+        // a degenerate hyperplane (e.g. normalized from a zero vector) isn't anywhere
+        if (0..4).any(|i| !normal.coord(i).is_finite()) || !hyperplane.param().is_finite() {
+            return keys;
+        }
+        let axis = (0..4)
+            .max_by(|&a, &b| {
+                normal
+                    .coord(a)
+                    .abs()
+                    .partial_cmp(&normal.coord(b).abs())
+                    .unwrap_or(Ordering::Equal)
+            })
+            .unwrap();
+        if normal.coord(axis) == 0.0 {
+            return keys;
+        }
+        let others: Vec<usize> = (0..4).filter(|&i| i != axis).collect();
+        let (low, high) = (self.min_cell, self.max_cell);
+        let mut column_low = low;
+        let mut column_high = high;
+        column_low[axis] = 0;
+        column_high[axis] = 0;
+        for column in SpatialIndex::keys_between(column_low, column_high) {
+            // the coordinate along `axis` where the hyperplane is, over the whole column
+            let (mut min, mut max) = (-hyperplane.param(), -hyperplane.param());
+            for &i in others.iter() {
+                let start = column[i] as f64 * self.cell_size;
+                let (a, b) = (
+                    -normal.coord(i) * start,
+                    -normal.coord(i) * (start + self.cell_size),
+                );
+                min += a.min(b);
+                max += a.max(b);
+            }
+            let (min, max) = if normal.coord(axis) > 0.0 {
+                (min / normal.coord(axis), max / normal.coord(axis))
+            } else {
+                (max / normal.coord(axis), min / normal.coord(axis))
+            };
+            let first = ((min / self.cell_size).floor() as i64).max(low[axis]);
+            let last = ((max / self.cell_size).floor() as i64).min(high[axis]);
+            for k in first..last + 1 {
+                let mut key = column;
+                key[axis] = k;
+                keys.push(key);
+            }
+        }
+        keys
+    }
+
+    /// The objects in the cells crossed by `hyperplane` - the boxes `query_hyperplane` tests.
+    fn hyperplane_candidates(&self, hyperplane: Hyperplane) -> Vec<usize> {
+        self.candidates(self.hyperplane_cells(hyperplane).into_iter())
+    }
+
+    /// The objects whose boxes are crossed by `hyperplane`.
+    pub fn query_hyperplane(&self, hyperplane: Hyperplane) -> Vec<usize> {
+        let mut result = self.hyperplane_candidates(hyperplane);
+        result.retain(|&i| self.boxes[i].crosses(hyperplane));
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::generator::Maze;
+    use super::super::levels::Level;
+    use super::super::objects::Collidable;
+    use super::SpatialIndex;
+    use graph4d::geometry::{Aabb, Hyperplane, Vector};
+
+    /// Boxes of different sizes scattered over a 40-unit hypercube.
+    fn boxes() -> Vec<Aabb> {
+        let mut state = 12345u64;
+        let mut next = || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 33) as f64 / (1u64 << 31) as f64
+        };
+        (0..200)
+            .map(|_| {
+                let corner =
+                    Vector::new(next() * 40.0, next() * 40.0, next() * 40.0, next() * 40.0);
+                let size = Vector::new(next() * 6.0, next() * 6.0, next() * 6.0, next() * 0.5);
+                Aabb::new(corner, corner + size)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_query_box() {
+        let boxes = boxes();
+        let index = SpatialIndex::new(&boxes);
+        let queries = [
+            Aabb::around(
+                Vector::new(10.0, 10.0, 10.0, 10.0),
+                Vector::new(14.0, 12.0, 20.0, 11.0),
+            ),
+            Aabb::around(
+                Vector::new(-5.0, -5.0, -5.0, -5.0),
+                Vector::new(0.0, 0.0, 0.0, 0.0),
+            ),
+            Aabb::around(
+                Vector::new(-1e6, 0.0, 0.0, 0.0),
+                Vector::new(1e6, 50.0, 50.0, 50.0),
+            ),
+        ];
+        for query in queries.iter() {
+            let expected: Vec<usize> = (0..boxes.len())
+                .filter(|&i| boxes[i].intersects(query))
+                .collect();
+            assert_eq!(index.query_box(query), expected);
+        }
+    }
+
+    #[test]
+    fn test_query_hyperplane() {
+        let boxes = boxes();
+        let index = SpatialIndex::new(&boxes);
+        let hyperplanes = [
+            Hyperplane::new(Vector::new(0.0, 0.0, 0.0, 1.0), -20.0),
+            Hyperplane::new(Vector::new(0.5, 0.0, 0.5, 0.5).normalized(), -30.0),
+        ];
+        for &hyperplane in hyperplanes.iter() {
+            let expected: Vec<usize> = (0..boxes.len())
+                .filter(|&i| boxes[i].crosses(hyperplane))
+                .collect();
+            let found = index.query_hyperplane(hyperplane);
+            assert!(!found.is_empty());
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn test_hyperplane_through_maze() {
+        let level = Level::from_cells(Maze::generate(8, 7).cells()).unwrap();
+        let boxes: Vec<Aabb> = level.walls().iter().map(|wall| wall.bounds()).collect();
+        let index = SpatialIndex::new(&boxes);
+        let hyperplanes = [
+            Hyperplane::new(Vector::new(0.0, 0.0, 0.0, 1.0), -7.5),
+            Hyperplane::new(Vector::new(0.0, 0.3, 0.1, 1.0).normalized(), -9.0),
+        ];
+        for &hyperplane in hyperplanes.iter() {
+            let expected: Vec<usize> = (0..boxes.len())
+                .filter(|&i| boxes[i].crosses(hyperplane))
+                .collect();
+            assert_eq!(index.query_hyperplane(hyperplane), expected);
+            // only a slice of the maze gets tested, not every wall
+            let tested = index.hyperplane_candidates(hyperplane).len();
+            assert!(
+                tested * 2 < boxes.len(),
+                "{} of {} walls tested",
+                tested,
+                boxes.len()
+            );
+        }
+    }
+
+    #[test]
+    fn test_degenerate_hyperplane() {
+        let index = SpatialIndex::new(&boxes());
+        let nan = Vector::new(0.0, 0.0, 0.0, 0.0).normalized();
+        assert!(index
+            .query_hyperplane(Hyperplane::new(nan, -20.0))
+            .is_empty());
+        let normal = Vector::new(0.0, 0.0, 0.0, 1.0);
+        let infinite = Hyperplane::new(normal, std::f64::INFINITY);
+        assert!(index.query_hyperplane(infinite).is_empty());
+    }
+
+    #[test]
+    fn test_empty() {
+        let index = SpatialIndex::new(&[]);
+        let query = Aabb::around(
+            Vector::new(0.0, 0.0, 0.0, 0.0),
+            Vector::new(1.0, 1.0, 1.0, 1.0),
+        );
+        assert!(index.query_box(&query).is_empty());
+    }
+}
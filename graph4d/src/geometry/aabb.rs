@@ -0,0 +1,282 @@
+use super::{Hyperplane, Matrix, Vector};
+
+const EPSILON: f64 = 1e-12;
+
+/// An axis-aligned box in 4D.
+#[derive(Clone, Copy)]
+pub struct Aabb {
+    min: Vector,
+    max: Vector,
+}
+
+/// The first point of contact of a swept point with a box.
+#[derive(Clone, Copy)]
+pub struct Contact {
+    /// The fraction of the motion made before the contact, between 0 and 1.
+    pub time: f64,
+    /// The outward normal of the face that was hit.
+    pub normal: Vector,
+}
+
+impl Aabb {
+    pub fn new(min: Vector, max: Vector) -> Aabb {
+        Aabb { min: min, max: max }
+    }
+
+    /// A box centered at `center`, extending `half_size` in both directions along every axis.
+    pub fn from_center(center: Vector, half_size: Vector) -> Aabb {
+        Aabb::new(center - half_size, center + half_size)
+    }
+
+    pub fn min(&self) -> Vector {
+        self.min
+    }
+
+    pub fn max(&self) -> Vector {
+        self.max
+    }
+
+    /// The smallest box containing both points.
+    pub fn around(a: Vector, b: Vector) -> Aabb {
+        Aabb::new(
+            Vector::new(
+                a.x().min(b.x()),
+                a.y().min(b.y()),
+                a.z().min(b.z()),
+                a.w().min(b.w()),
+            ),
+            Vector::new(
+                a.x().max(b.x()),
+                a.y().max(b.y()),
+                a.z().max(b.z()),
+                a.w().max(b.w()),
+            ),
+        )
+    }
+
+    fn corner(&self, corner: usize) -> Vector {
+        let pick = |i: usize| {
+            if corner & (1 << i) == 0 {
+                self.min.coord(i)
+            } else {
+                self.max.coord(i)
+            }
+        };
+        Vector::new(pick(0), pick(1), pick(2), pick(3))
+    }
+
+    /// The smallest axis-aligned box containing this one after transforming it by `matrix`.
+    pub fn transformed(&self, matrix: Matrix) -> Aabb {
+        let first = matrix * self.corner(0);
+        (1..16).fold(Aabb::new(first, first), |aabb, corner| {
+            let p = matrix * self.corner(corner);
+            Aabb::new(Aabb::around(aabb.min, p).min, Aabb::around(aabb.max, p).max)
+        })
+    }
+
+    /// Whether the boxes overlap or touch.
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        (0..4).all(|i| {
+            self.min.coord(i) <= other.max.coord(i) && other.min.coord(i) <= self.max.coord(i)
+        })
+    }
+
+    /// Whether the hyperplane passes through the box, or touches it.
+    pub fn crosses(&self, hyperplane: Hyperplane) -> bool {
+        let mut below = false;
+        let mut above = false;
+        for corner in 0..16 {
+            let side = hyperplane.dot(self.corner(corner));
+            below |= side <= 0.0;
+            above |= side >= 0.0;
+        }
+        below && above
+    }
+
+    /// Whether `point` is strictly inside the box - points on the boundary aren't.
+    pub fn contains(&self, point: Vector) -> bool {
+        (0..4).all(|i| point.coord(i) > self.min.coord(i) && point.coord(i) < self.max.coord(i))
+    }
+
+    /// Finds where a point moving from `start` by `motion` first enters the box.
+    ///
+    /// Only entering counts: a point starting inside, or just sliding along a face, doesn't
+    /// hit anything.
+    pub fn sweep(&self, start: Vector, motion: Vector) -> Option<Contact> {
+        let mut enter = 0.0;
+        let mut exit = 1.0;
+        let mut normal = None;
+
+        for i in 0..4 {
+            let (s, d) = (start.coord(i), motion.coord(i));
+            let (min, max) = (self.min.coord(i), self.max.coord(i));
+            if d.abs() < EPSILON {
+                if s <= min || s >= max {
+                    return None;
+                }
+                continue;
+            }
+            let (t_min, t_max) = ((min - s) / d, (max - s) / d);
+            let (t_in, t_out) = if d > 0.0 {
+                (t_min, t_max)
+            } else {
+                (t_max, t_min)
+            };
+            if t_in >= enter {
+                enter = t_in;
+                let mut n = [0.0; 4];
+                n[i] = -d.signum();
+                normal = Some(Vector::new(n[0], n[1], n[2], n[3]));
+            }
+            if t_out < exit {
+                exit = t_out;
+            }
+            if enter >= exit {
+                return None;
+            }
+        }
+
+        normal.map(|normal| Contact {
+            time: enter,
+            normal: normal,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{Hyperplane, Matrix, Vector};
+    use super::Aabb;
+
+    fn unit_box() -> Aabb {
+        Aabb::from_center(
+            Vector::new(0.0, 0.0, 0.0, 0.0),
+            Vector::new(1.0, 1.0, 1.0, 1.0),
+        )
+    }
+
+    #[test]
+    fn test_contains() {
+        let aabb = unit_box();
+        assert!(aabb.contains(Vector::new(0.5, -0.5, 0.9, 0.0)));
+        assert!(!aabb.contains(Vector::new(1.0, 0.0, 0.0, 0.0)));
+        assert!(!aabb.contains(Vector::new(0.0, 0.0, 0.0, -1.5)));
+    }
+
+    #[test]
+    fn test_intersects() {
+        let aabb = unit_box();
+        let other = Aabb::around(
+            Vector::new(0.5, 0.5, 0.5, 0.5),
+            Vector::new(3.0, 2.0, 2.0, 2.0),
+        );
+        assert!(aabb.intersects(&other) && other.intersects(&aabb));
+        let touching = Aabb::around(
+            Vector::new(1.0, 0.0, 0.0, 0.0),
+            Vector::new(2.0, 1.0, 1.0, 1.0),
+        );
+        assert!(aabb.intersects(&touching));
+        let apart = Aabb::around(
+            Vector::new(0.0, 0.0, 0.0, 1.5),
+            Vector::new(1.0, 1.0, 1.0, 2.0),
+        );
+        assert!(!aabb.intersects(&apart));
+    }
+
+    #[test]
+    fn test_transformed() {
+        let aabb = Aabb::new(
+            Vector::new(0.0, 0.0, 0.0, 0.0),
+            Vector::new(2.0, 1.0, 1.0, 0.0),
+        );
+        let matrix = Matrix::translation(Vector::new(1.0, 1.0, 1.0, 1.0))
+            * Matrix::rotation_xw(std::f64::consts::PI / 2.0);
+        let moved = aabb.transformed(matrix);
+        assert!(moved.min() == Vector::new(1.0, 1.0, 1.0, 1.0));
+        assert!(moved.max() == Vector::new(1.0, 2.0, 2.0, 3.0));
+    }
+
+    #[test]
+    fn test_crosses() {
+        let aabb = unit_box();
+        let w = Vector::new(0.0, 0.0, 0.0, 1.0);
+        assert!(aabb.crosses(Hyperplane::new(w, 0.5)));
+        assert!(aabb.crosses(Hyperplane::new(w, -1.0)));
+        assert!(!aabb.crosses(Hyperplane::new(w, -1.5)));
+        // a slanted hyperplane passing near a corner
+        let diagonal = Vector::new(1.0, 1.0, 1.0, 1.0);
+        assert!(aabb.crosses(Hyperplane::new(diagonal, -3.9)));
+        assert!(!aabb.crosses(Hyperplane::new(diagonal, -4.1)));
+    }
+
+    #[test]
+    fn test_sweep_hit() {
+        let contact = unit_box()
+            .sweep(
+                Vector::new(-3.0, 0.0, 0.5, 0.0),
+                Vector::new(4.0, 0.0, 0.0, 0.0),
+            )
+            .unwrap();
+        assert!((contact.time - 0.5).abs() < 1e-9);
+        assert!(contact.normal == Vector::new(-1.0, 0.0, 0.0, 0.0));
+
+        // the last slab entered gives the normal
+        let contact = unit_box()
+            .sweep(
+                Vector::new(0.0, 0.0, 0.0, 3.0),
+                Vector::new(0.5, 0.0, 0.0, -4.0),
+            )
+            .unwrap();
+        assert!((contact.time - 0.5).abs() < 1e-9);
+        assert!(contact.normal == Vector::new(0.0, 0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_sweep_miss() {
+        let aabb = unit_box();
+        // stops short
+        assert!(aabb
+            .sweep(
+                Vector::new(-3.0, 0.0, 0.0, 0.0),
+                Vector::new(1.5, 0.0, 0.0, 0.0)
+            )
+            .is_none());
+        // passes beside
+        assert!(aabb
+            .sweep(
+                Vector::new(-3.0, 2.0, 0.0, 0.0),
+                Vector::new(6.0, 0.0, 0.0, 0.0)
+            )
+            .is_none());
+        // slides along a face
+        assert!(aabb
+            .sweep(
+                Vector::new(-3.0, 1.0, 0.0, 0.0),
+                Vector::new(6.0, 0.0, 0.0, 0.0)
+            )
+            .is_none());
+        // starts inside
+        assert!(aabb
+            .sweep(
+                Vector::new(0.0, 0.0, 0.0, 0.0),
+                Vector::new(6.0, 0.0, 0.0, 0.0)
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn test_sweep_thin_box() {
+        // a thin box is still hit, however long the motion
+        let wall = Aabb::new(
+            Vector::new(-1.0, -1.0, -1.0, -0.01),
+            Vector::new(1.0, 1.0, 1.0, 0.01),
+        );
+        let contact = wall
+            .sweep(
+                Vector::new(0.0, 0.0, 0.0, -1.0),
+                Vector::new(0.0, 0.0, 0.0, 1000.0),
+            )
+            .unwrap();
+        assert!((contact.time - 0.00099).abs() < 1e-9);
+    }
+}
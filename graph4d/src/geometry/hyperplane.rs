@@ -14,6 +14,14 @@ impl Hyperplane {
         }
     }
 
+    pub fn normal(&self) -> Vector {
+        self.normal
+    }
+
+    pub fn param(&self) -> f64 {
+        self.param
+    }
+
     pub fn dot(&self, v: Vector) -> f64 {
         self.normal.dot(v) + self.param
     }
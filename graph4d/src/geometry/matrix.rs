@@ -23,6 +23,10 @@ impl Matrix {
         Matrix { coords: arr }
     }
 
+    pub fn as_array(&self) -> &[[f64; 5]; 5] {
+        &self.coords
+    }
+
     pub fn rotation(n1: Vector, n2: Vector, phi: f64) -> Matrix {
         let n1 = n1.normalized();
         let n2 = n2.normalized();
@@ -150,6 +154,43 @@ impl Matrix {
         }
     }
 
+    /// Checks whether the columns of the rotation part (the upper left 4x4 block) are
+    /// orthonormal, up to `epsilon`.
+    pub fn is_orthonormal(&self, epsilon: f64) -> bool {
+        (0..4).all(|j| {
+            (0..4).all(|k| {
+                let dot: f64 = self.coords[..4].iter().map(|row| row[j] * row[k]).sum();
+                let expected = if j == k { 1.0 } else { 0.0 };
+                (dot - expected).abs() < epsilon
+            })
+        })
+    }
+
+    /// Makes the columns of the rotation part orthonormal again with the Gram-Schmidt process,
+    /// starting from the first column. Products of many rotations slowly drift away from a
+    /// rotation because of rounding errors; this brings them back. The rest of the matrix is
+    /// left as it is.
+    pub fn orthonormalized(&self) -> Matrix {
+        let mut coords = self.coords;
+        for j in 0..4 {
+            for k in 0..j {
+                let dot: f64 = coords[..4].iter().map(|row| row[j] * row[k]).sum();
+                for row in coords[..4].iter_mut() {
+                    row[j] -= dot * row[k];
+                }
+            }
+            let len = coords[..4]
+                .iter()
+                .map(|row| row[j] * row[j])
+                .sum::<f64>()
+                .sqrt();
+            for row in coords[..4].iter_mut() {
+                row[j] /= len;
+            }
+        }
+        Matrix { coords: coords }
+    }
+
     pub fn translation(v: Vector) -> Matrix {
         let mut result = Matrix::identity();
         result.coords[0][4] = v.x();
@@ -390,4 +431,37 @@ mod test {
         assert_eq!(c.coord(3), 1.0);
         assert_eq!(c.coord(4), 4.0);
     }
+
+    #[test]
+    fn test_orthonormalized() {
+        let rotation = Matrix::rotation(
+            Vector::new(1.0, 2.0, 0.0, -1.0),
+            Vector::new(0.0, 1.0, 3.0, 1.0),
+            0.7,
+        ) * Matrix::translation(Vector::new(1.0, 2.0, 3.0, 4.0));
+        assert!(rotation.is_orthonormal(1e-12));
+        let same = rotation.orthonormalized();
+        for i in 0..5 {
+            for j in 0..5 {
+                assert!((same.coords[i][j] - rotation.coords[i][j]).abs() < 1e-12);
+            }
+        }
+
+        let mut skewed = rotation;
+        skewed.coords[0][1] += 0.01;
+        skewed.coords[2][3] -= 0.02;
+        skewed.coords[3][3] *= 1.01;
+        assert!(!skewed.is_orthonormal(1e-3));
+        let fixed = skewed.orthonormalized();
+        assert!(fixed.is_orthonormal(1e-12));
+        for i in 0..5 {
+            // the first column only gets normalized, the translation stays
+            assert_eq!(fixed.coords[i][4], skewed.coords[i][4]);
+            assert_eq!(fixed.coords[4][i], skewed.coords[4][i]);
+            assert!((fixed.coords[i][0] - rotation.coords[i][0]).abs() < 1e-12);
+            for j in 0..4 {
+                assert!((fixed.coords[i][j] - rotation.coords[i][j]).abs() < 0.05);
+            }
+        }
+    }
 }
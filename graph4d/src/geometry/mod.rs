@@ -1,7 +1,11 @@
+pub mod aabb;
 pub mod hyperplane;
 pub mod matrix;
+pub mod rotor;
 pub mod vector;
 
+pub use self::aabb::{Aabb, Contact};
 pub use self::hyperplane::Hyperplane;
 pub use self::matrix::Matrix;
+pub use self::rotor::Rotor;
 pub use self::vector::Vector;
@@ -0,0 +1,456 @@
+use super::matrix::Matrix;
+use super::vector::Vector;
+use std::f64::consts::{PI, SQRT_2};
+use std::ops;
+
+/// Below this, angles and lengths are treated as zero.
+const EPSILON: f64 = 1e-12;
+
+/// The basis blades of the even subalgebra, in the order of `Rotor::coords`. Every bit stands
+/// for one of the basis vectors: 1 for x, 2 for y, 4 for z and 8 for w.
+const BLADES: [usize; 8] = [
+    0b0000, 0b0011, 0b0101, 0b1001, 0b0110, 0b1010, 0b1100, 0b1111,
+];
+
+/// An element of the whole geometric algebra Cl(4,0), with the coefficient of every basis blade
+/// stored at the index given by its bits.
+type Multivector = [f64; 16];
+
+/// The sign of the product of two basis blades: -1 if an odd number of basis vectors have to
+/// swap places to bring the product into the canonical order.
+fn product_sign(mut a: usize, b: usize) -> f64 {
+    let mut swaps = 0;
+    a >>= 1;
+    while a != 0 {
+        swaps += (a & b).count_ones();
+        a >>= 1;
+    }
+    if swaps % 2 == 0 {
+        1.0
+    } else {
+        -1.0
+    }
+}
+
+fn product(a: &Multivector, b: &Multivector) -> Multivector {
+    let mut result = [0.0; 16];
+    for (i, &x) in a.iter().enumerate().filter(|&(_, &x)| x != 0.0) {
+        for (j, &y) in b.iter().enumerate().filter(|&(_, &y)| y != 0.0) {
+            result[i ^ j] += product_sign(i, j) * x * y;
+        }
+    }
+    result
+}
+
+fn from_vector(v: Vector) -> Multivector {
+    let mut result = [0.0; 16];
+    result[0b0001] = v.x();
+    result[0b0010] = v.y();
+    result[0b0100] = v.z();
+    result[0b1000] = v.w();
+    result
+}
+
+/// A rotation of the 4D space around the origin, as an element of the even subalgebra of
+/// Cl(4,0): a scalar, six bivector coefficients (xy, xz, xw, yz, yw, zw) and a pseudoscalar
+/// (xyzw) one.
+///
+/// A vector `v` is rotated to `R v R~`, `R~` being the reverse of `R`. Unlike matrices, rotors
+/// can be interpolated smoothly with `slerp`. Like a product of matrices, `a * b` rotates by `b`
+/// first and then by `a`. `R` and `-R` are the same rotation.
+///
+/// A rotation in a single plane only has scalar and bivector coefficients. A double rotation,
+/// in two orthogonal planes at once, is the product of the rotations in both planes and has a
+/// pseudoscalar coefficient too.
+#[derive(Clone, Copy)]
+pub struct Rotor {
+    coords: [f64; 8],
+}
+
+impl Rotor {
+    pub fn identity() -> Rotor {
+        Rotor {
+            coords: [1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+        }
+    }
+
+    fn pseudoscalar() -> Rotor {
+        Rotor {
+            coords: [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0],
+        }
+    }
+
+    fn from_multivector(m: &Multivector) -> Rotor {
+        let mut coords = [0.0; 8];
+        for (coord, &blade) in coords.iter_mut().zip(BLADES.iter()) {
+            *coord = m[blade];
+        }
+        Rotor { coords: coords }
+    }
+
+    fn to_multivector(self) -> Multivector {
+        let mut result = [0.0; 16];
+        for (&coord, &blade) in self.coords.iter().zip(BLADES.iter()) {
+            result[blade] = coord;
+        }
+        result
+    }
+
+    fn from_bivector(bivector: &[f64]) -> Rotor {
+        let mut coords = [0.0; 8];
+        coords[1..7].copy_from_slice(bivector);
+        Rotor { coords: coords }
+    }
+
+    /// Rotation by `phi` in the plane of `a` and `b`, turning `a` towards `b`.
+    pub fn from_plane(a: Vector, b: Vector, phi: f64) -> Rotor {
+        let a = a.normalized();
+        let b = (b - a * a.dot(b)).normalized();
+        let plane = product(&from_vector(a), &from_vector(b));
+        let mut result = Rotor::from_multivector(&plane) * -(phi / 2.0).sin();
+        result.coords[0] = (phi / 2.0).cos();
+        result
+    }
+
+    /// The same rotation as `Matrix::rotation`: by `phi` in the plane orthogonal to `n1` and
+    /// `n2`.
+    pub fn rotation(n1: Vector, n2: Vector, phi: f64) -> Rotor {
+        let n1 = n1.normalized();
+        let n2 = (n2 - n1 * n1.dot(n2)).normalized();
+        let plane = Rotor::from_multivector(&product(&from_vector(n1), &from_vector(n2)));
+        let mut result = Rotor::pseudoscalar() * plane * (phi / 2.0).sin();
+        result.coords[0] = (phi / 2.0).cos();
+        result
+    }
+
+    /// The rotor of a rotation matrix. Only the rotation part (the upper left 4x4 block) of the
+    /// matrix is used, and it has to be orthonormal with a determinant of 1.
+    pub fn from_matrix(matrix: &Matrix) -> Rotor {
+        // the images of all the basis blades: the products of the images of their vectors
+        let coords = matrix.as_array();
+        let mut images = [[0.0; 16]; 16];
+        images[0][0] = 1.0;
+        for blade in 1..16usize {
+            let i = blade.trailing_zeros() as usize;
+            let mut column = [0.0; 16];
+            for (j, row) in coords[..4].iter().enumerate() {
+                column[1 << j] = row[i];
+            }
+            images[blade] = product(&column, &images[blade & (blade - 1)]);
+        }
+
+        // With `E` going over all the basis blades, the sum of `R E R~ Y E^-1` is `R` times
+        // 16 times the scalar part of `R~ Y`. One of the even blades `Y` gives a non-zero
+        // scalar part - the one with the largest sum is the most precise.
+        let mut best = Rotor::identity();
+        let mut best_norm = 0.0;
+        for &y in BLADES.iter() {
+            let mut sum = [0.0; 16];
+            for (blade, image) in images.iter().enumerate() {
+                let mut y_blade = [0.0; 16];
+                y_blade[y] = 1.0;
+                let mut inverse = [0.0; 16];
+                let grade = blade.count_ones();
+                inverse[blade] = if grade % 4 < 2 { 1.0 } else { -1.0 };
+                let term = product(&product(image, &y_blade), &inverse);
+                for (s, t) in sum.iter_mut().zip(term.iter()) {
+                    *s += t;
+                }
+            }
+            let candidate = Rotor::from_multivector(&sum);
+            if candidate.norm() > best_norm {
+                best_norm = candidate.norm();
+                best = candidate;
+            }
+        }
+        best.normalized()
+    }
+
+    pub fn to_matrix(self) -> Matrix {
+        let mut coords = [[0.0; 5]; 5];
+        let axes = [
+            Vector::new(1.0, 0.0, 0.0, 0.0),
+            Vector::new(0.0, 1.0, 0.0, 0.0),
+            Vector::new(0.0, 0.0, 1.0, 0.0),
+            Vector::new(0.0, 0.0, 0.0, 1.0),
+        ];
+        for (j, &axis) in axes.iter().enumerate() {
+            let image = self.rotate(axis);
+            for (i, row) in coords.iter_mut().enumerate().take(4) {
+                row[j] = image.coord(i);
+            }
+        }
+        coords[4][4] = 1.0;
+        Matrix::from_array(coords)
+    }
+
+    pub fn rotate(self, v: Vector) -> Vector {
+        let result = product(
+            &product(&self.to_multivector(), &from_vector(v)),
+            &self.reverse().to_multivector(),
+        );
+        Vector::new(
+            result[0b0001],
+            result[0b0010],
+            result[0b0100],
+            result[0b1000],
+        )
+    }
+
+    fn norm(self) -> f64 {
+        self.coords.iter().map(|x| x * x).sum::<f64>().sqrt()
+    }
+
+    pub fn normalized(self) -> Rotor {
+        self * (1.0 / self.norm())
+    }
+
+    fn reverse(mut self) -> Rotor {
+        for coord in self.coords[1..7].iter_mut() {
+            *coord = -*coord;
+        }
+        self
+    }
+
+    pub fn inverse(self) -> Rotor {
+        let norm = self.norm();
+        self.reverse() * (1.0 / (norm * norm))
+    }
+
+    /// Splits the rotor into its parts in the two ideals of the even subalgebra, given by the
+    /// projectors `(1 + I) / 2` and `(1 - I) / 2`, `I` being the pseudoscalar. Each part
+    /// behaves like a unit quaternion, with its own angle.
+    fn split(self) -> [Rotor; 2] {
+        let dual = self * Rotor::pseudoscalar();
+        let mut parts = [self, self];
+        for (part, &sign) in parts.iter_mut().zip([1.0, -1.0].iter()) {
+            for (coord, &other) in part.coords.iter_mut().zip(dual.coords.iter()) {
+                *coord = (*coord + sign * other) / 2.0;
+            }
+        }
+        parts
+    }
+
+    /// The bivector `B` such that `exp(B)` is this rotor.
+    fn log(self) -> [f64; 6] {
+        let mut result = [0.0; 6];
+        for (part, &sign) in self.split().iter().zip([1.0, -1.0].iter()) {
+            // the part is `P cos(angle) + U sin(angle)`, where `P` is the projector and `U` is
+            // a bivector with `U^2 = -P` and a length of 1/sqrt(2)
+            let cos = 2.0 * part.coords[0];
+            let sin = SQRT_2 * Rotor::from_bivector(&part.coords[1..7]).norm();
+            let angle = sin.atan2(cos);
+            let bivector = if sin > EPSILON {
+                Rotor::from_bivector(&part.coords[1..7]) * (angle / sin)
+            } else if cos < 0.0 {
+                // a half turn: any plane will do
+                let plane = Rotor::from_bivector(&[1.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+                let dual = plane * Rotor::pseudoscalar();
+                (plane + dual * sign) * (PI / 2.0)
+            } else {
+                continue;
+            };
+            for (r, b) in result.iter_mut().zip(bivector.coords[1..7].iter()) {
+                *r += b;
+            }
+        }
+        result
+    }
+
+    fn exp(bivector: &[f64; 6]) -> Rotor {
+        let mut result = Rotor { coords: [0.0; 8] };
+        for (part, &sign) in Rotor::from_bivector(bivector)
+            .split()
+            .iter()
+            .zip([1.0, -1.0].iter())
+        {
+            let angle = SQRT_2 * part.norm();
+            let sinc = if angle > EPSILON {
+                angle.sin() / angle
+            } else {
+                1.0
+            };
+            let mut rotated = *part * sinc;
+            rotated.coords[0] = angle.cos() / 2.0;
+            rotated.coords[7] = sign * angle.cos() / 2.0;
+            result = result + rotated;
+        }
+        result
+    }
+
+    /// Interpolates between two rotations along the shortest way, at a constant angular speed:
+    /// `t` = 0 gives this rotor and `t` = 1 gives `other`.
+    pub fn slerp(self, other: Rotor, t: f64) -> Rotor {
+        let delta = self.inverse() * other;
+        let log = delta.log();
+        let log_negated = (delta * -1.0).log();
+        let norm = |b: &[f64; 6]| Rotor::from_bivector(b).norm();
+        let mut log = if norm(&log_negated) < norm(&log) {
+            log_negated
+        } else {
+            log
+        };
+        for coord in log.iter_mut() {
+            *coord *= t;
+        }
+        self * Rotor::exp(&log)
+    }
+}
+
+impl ops::Add<Rotor> for Rotor {
+    type Output = Rotor;
+
+    fn add(mut self, other: Rotor) -> Rotor {
+        for (coord, other) in self.coords.iter_mut().zip(other.coords.iter()) {
+            *coord += other;
+        }
+        self
+    }
+}
+
+impl ops::Mul<f64> for Rotor {
+    type Output = Rotor;
+
+    fn mul(mut self, other: f64) -> Rotor {
+        for coord in self.coords.iter_mut() {
+            *coord *= other;
+        }
+        self
+    }
+}
+
+impl ops::Mul<Rotor> for Rotor {
+    type Output = Rotor;
+
+    fn mul(self, other: Rotor) -> Rotor {
+        Rotor::from_multivector(&product(&self.to_multivector(), &other.to_multivector()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{Matrix, Vector};
+    use super::Rotor;
+    use std::f64::consts::PI;
+
+    fn assert_close(a: Matrix, b: Matrix) {
+        for (row_a, row_b) in a.as_array().iter().zip(b.as_array().iter()) {
+            for (x, y) in row_a.iter().zip(row_b.iter()) {
+                assert!(
+                    (x - y).abs() < 1e-9,
+                    "{:?} != {:?}",
+                    a.as_array(),
+                    b.as_array()
+                );
+            }
+        }
+    }
+
+    fn axis(i: usize) -> Vector {
+        let mut coords = [0.0; 5];
+        coords[i] = 1.0;
+        coords[4] = 1.0;
+        Vector::from_array(coords)
+    }
+
+    fn double_rotation(phi: f64, psi: f64) -> Rotor {
+        Rotor::from_plane(axis(0), axis(1), phi) * Rotor::from_plane(axis(2), axis(3), psi)
+    }
+
+    #[test]
+    fn test_plane_rotations() {
+        let matrices = [
+            (0, 1, Matrix::rotation_xy(0.3)),
+            (0, 2, Matrix::rotation_xz(0.3)),
+            (0, 3, Matrix::rotation_xw(0.3)),
+            (1, 2, Matrix::rotation_yz(0.3)),
+            (1, 3, Matrix::rotation_yw(0.3)),
+            (2, 3, Matrix::rotation_zw(0.3)),
+        ];
+        for &(i, j, matrix) in matrices.iter() {
+            assert_close(Rotor::from_plane(axis(i), axis(j), 0.3).to_matrix(), matrix);
+        }
+
+        let n1 = Vector::new(1.0, 2.0, 0.0, -1.0);
+        let n2 = Vector::new(0.0, 1.0, 3.0, 1.0);
+        assert_close(
+            Rotor::rotation(n1, n2, 0.8).to_matrix(),
+            Matrix::rotation(n1, n2, 0.8),
+        );
+        let v = Vector::new(0.5, -1.0, 2.0, 0.25);
+        assert!(Rotor::rotation(n1, n2, 0.8).rotate(v) == Matrix::rotation(n1, n2, 0.8) * v);
+    }
+
+    #[test]
+    fn test_composition() {
+        let a = Rotor::rotation(
+            Vector::new(1.0, 2.0, 0.0, -1.0),
+            Vector::new(0.0, 1.0, 3.0, 1.0),
+            0.8,
+        );
+        let b = double_rotation(1.2, -0.4);
+        assert_close((a * b).to_matrix(), a.to_matrix() * b.to_matrix());
+        assert_close((b * a).to_matrix(), b.to_matrix() * a.to_matrix());
+        assert_close((a * a.inverse()).to_matrix(), Matrix::identity());
+        assert_close((b.inverse() * b).to_matrix(), Matrix::identity());
+        // rotations in orthogonal planes don't depend on the order
+        let c =
+            Rotor::from_plane(axis(2), axis(3), -0.4) * Rotor::from_plane(axis(0), axis(1), 1.2);
+        assert_close(b.to_matrix(), c.to_matrix());
+    }
+
+    #[test]
+    fn test_from_matrix() {
+        let rotors = [
+            Rotor::identity(),
+            Rotor::from_plane(axis(1), axis(3), 0.5),
+            Rotor::rotation(
+                Vector::new(1.0, 2.0, 0.0, -1.0),
+                Vector::new(0.0, 1.0, 3.0, 1.0),
+                2.0,
+            ),
+            double_rotation(0.7, 2.1),
+            // isoclinic rotations, by the same angle in both planes
+            double_rotation(1.0, 1.0),
+            double_rotation(1.0, -1.0),
+            // half turns have no scalar part, and the double one is only a pseudoscalar
+            Rotor::from_plane(axis(0), axis(2), PI),
+            double_rotation(PI, PI),
+        ];
+        for rotor in rotors.iter() {
+            let matrix = rotor.to_matrix();
+            assert_close(Rotor::from_matrix(&matrix).to_matrix(), matrix);
+        }
+    }
+
+    #[test]
+    fn test_slerp() {
+        let a = Rotor::from_plane(axis(0), axis(3), 0.3);
+        let b = double_rotation(1.5, -0.6);
+        assert_close(a.slerp(b, 0.0).to_matrix(), a.to_matrix());
+        assert_close(a.slerp(b, 1.0).to_matrix(), b.to_matrix());
+
+        // a double rotation is interpolated in both planes at once
+        let start = Rotor::identity();
+        assert_close(
+            start.slerp(b, 0.5).to_matrix(),
+            double_rotation(0.75, -0.3).to_matrix(),
+        );
+        assert_close(
+            start.slerp(b * -1.0, 0.25).to_matrix(),
+            double_rotation(0.375, -0.15).to_matrix(),
+        );
+
+        // at a constant speed
+        let quarter = a.inverse() * a.slerp(b, 0.25);
+        let half = a.slerp(b, 0.5);
+        assert_close((a * quarter * quarter).to_matrix(), half.to_matrix());
+
+        // the shortest way round: a turn by 3/2 pi is a quarter turn the other way
+        let far = Rotor::from_plane(axis(0), axis(1), 1.5 * PI);
+        assert_close(
+            start.slerp(far, 0.5).to_matrix(),
+            Rotor::from_plane(axis(0), axis(1), -PI / 4.0).to_matrix(),
+        );
+    }
+}
@@ -4,5 +4,8 @@ extern crate glium;
 pub mod camera;
 pub mod geometry;
 pub mod primitive;
+pub mod projection;
+pub mod raster;
 pub mod renderer;
 pub mod shader;
+pub mod slice;
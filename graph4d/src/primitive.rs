@@ -355,21 +355,33 @@ impl Primitive {
         )
     }
 
-    fn vertexinfo_triangle(p1: Vertex, p2: Vertex, p3: Vertex) -> VertexInfo {
+    fn push_triangle(
+        p1: Vertex,
+        p2: Vertex,
+        p3: Vertex,
+        vertices: &mut Vec<GliumVertex>,
+        indices: &mut Vec<u32>,
+    ) {
         let v1 = p2.point() - p1.point();
         let v2 = p3.point() - p1.point();
         let normal = Vector::cross3(v2, v1).normalized();
-        VertexInfo::new(
-            vec![
-                GliumVertex::new(p1.point(), normal, p1.color()),
-                GliumVertex::new(p2.point(), normal, p2.color()),
-                GliumVertex::new(p3.point(), normal, p3.color()),
-            ],
-            vec![0, 1, 2],
-        )
+        let offset = vertices.len() as u32;
+        vertices.extend_from_slice(&[
+            GliumVertex::new(p1.point(), normal, p1.color()),
+            GliumVertex::new(p2.point(), normal, p2.color()),
+            GliumVertex::new(p3.point(), normal, p3.color()),
+        ]);
+        indices.extend([0, 1, 2].iter().map(|i| i + offset));
     }
 
-    fn vertexinfo_quad(p1: Vertex, p2: Vertex, p3: Vertex, p4: Vertex) -> VertexInfo {
+    fn push_quad(
+        p1: Vertex,
+        p2: Vertex,
+        p3: Vertex,
+        p4: Vertex,
+        vertices: &mut Vec<GliumVertex>,
+        indices: &mut Vec<u32>,
+    ) {
         let v1 = (p2.point() - p1.point()).normalized();
         let v2 = (p3.point() - p1.point()).normalized();
         let v3 = (p4.point() - p1.point()).normalized();
@@ -377,50 +389,33 @@ impl Primitive {
         let mindot = if dot[0] < dot[1] { 0 } else { 1 };
         let mindot = if dot[mindot] < dot[2] { mindot } else { 2 };
 
-        let mut indices = Vec::new();
-
-        match mindot {
-            0 => {
-                indices.push(0);
-                indices.push(1);
-                indices.push(2);
-                indices.push(1);
-                indices.push(2);
-                indices.push(3);
-            }
-            1 => {
-                indices.push(0);
-                indices.push(1);
-                indices.push(3);
-                indices.push(1);
-                indices.push(3);
-                indices.push(2);
-            }
-            2 => {
-                indices.push(0);
-                indices.push(2);
-                indices.push(3);
-                indices.push(2);
-                indices.push(3);
-                indices.push(1);
-            }
+        let order = match mindot {
+            0 => [0, 1, 2, 1, 2, 3],
+            1 => [0, 1, 3, 1, 3, 2],
+            2 => [0, 2, 3, 2, 3, 1],
             _ => unreachable!(),
         };
 
         let normal = Vector::cross3(v2, v1).normalized();
 
-        VertexInfo::new(
-            vec![
-                GliumVertex::new(p1.point(), normal, p1.color()),
-                GliumVertex::new(p2.point(), normal, p2.color()),
-                GliumVertex::new(p3.point(), normal, p3.color()),
-                GliumVertex::new(p4.point(), normal, p4.color()),
-            ],
-            indices,
-        )
+        let offset = vertices.len() as u32;
+        vertices.extend_from_slice(&[
+            GliumVertex::new(p1.point(), normal, p1.color()),
+            GliumVertex::new(p2.point(), normal, p2.color()),
+            GliumVertex::new(p3.point(), normal, p3.color()),
+            GliumVertex::new(p4.point(), normal, p4.color()),
+        ]);
+        indices.extend(order.iter().map(|i| i + offset));
     }
 
-    fn vertexinfo_tetra(p1: Vertex, p2: Vertex, p3: Vertex, p4: Vertex) -> VertexInfo {
+    fn push_tetra(
+        p1: Vertex,
+        p2: Vertex,
+        p3: Vertex,
+        p4: Vertex,
+        vertices: &mut Vec<GliumVertex>,
+        indices: &mut Vec<u32>,
+    ) {
         let v21 = p2.point() - p1.point();
         let v31 = p3.point() - p1.point();
         let v41 = p4.point() - p1.point();
@@ -430,32 +425,62 @@ impl Primitive {
         let normal2 = Vector::cross3(v41, v31).normalized();
         let normal3 = Vector::cross3(v21, v41).normalized();
         let normal4 = Vector::cross3(v43, v23).normalized();
-        VertexInfo::new(
-            vec![
-                GliumVertex::new(p1.point(), normal1, p1.color()),
-                GliumVertex::new(p2.point(), normal1, p2.color()),
-                GliumVertex::new(p3.point(), normal1, p3.color()),
-                GliumVertex::new(p1.point(), normal2, p1.color()),
-                GliumVertex::new(p3.point(), normal2, p3.color()),
-                GliumVertex::new(p4.point(), normal2, p4.color()),
-                GliumVertex::new(p1.point(), normal3, p1.color()),
-                GliumVertex::new(p4.point(), normal3, p4.color()),
-                GliumVertex::new(p2.point(), normal3, p2.color()),
-                GliumVertex::new(p3.point(), normal4, p3.color()),
-                GliumVertex::new(p2.point(), normal4, p2.color()),
-                GliumVertex::new(p4.point(), normal4, p4.color()),
-            ],
-            vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
-        )
+        let offset = vertices.len() as u32;
+        vertices.extend_from_slice(&[
+            GliumVertex::new(p1.point(), normal1, p1.color()),
+            GliumVertex::new(p2.point(), normal1, p2.color()),
+            GliumVertex::new(p3.point(), normal1, p3.color()),
+            GliumVertex::new(p1.point(), normal2, p1.color()),
+            GliumVertex::new(p3.point(), normal2, p3.color()),
+            GliumVertex::new(p4.point(), normal2, p4.color()),
+            GliumVertex::new(p1.point(), normal3, p1.color()),
+            GliumVertex::new(p4.point(), normal3, p4.color()),
+            GliumVertex::new(p2.point(), normal3, p2.color()),
+            GliumVertex::new(p3.point(), normal4, p3.color()),
+            GliumVertex::new(p2.point(), normal4, p2.color()),
+            GliumVertex::new(p4.point(), normal4, p4.color()),
+        ]);
+        indices.extend((0..12).map(|i| i + offset));
+    }
+
+    /// Appends the triangles of the primitive to `vertices` and `indices`, with the indices
+    /// pointing past the vertices already there. Points and lines have no triangles.
+    pub fn push_triangles(&self, vertices: &mut Vec<GliumVertex>, indices: &mut Vec<u32>) {
+        match *self {
+            Primitive::Point(_) | Primitive::Line(..) => (),
+            Primitive::Triangle(p1, p2, p3) => {
+                Primitive::push_triangle(p1, p2, p3, vertices, indices)
+            }
+            Primitive::Quad(p1, p2, p3, p4) => {
+                Primitive::push_quad(p1, p2, p3, p4, vertices, indices)
+            }
+            Primitive::Tetra(p1, p2, p3, p4) => {
+                Primitive::push_tetra(p1, p2, p3, p4, vertices, indices)
+            }
+        }
+    }
+
+    /// The color of the first vertex.
+    pub fn color(&self) -> Color {
+        match *self {
+            Primitive::Point(p1)
+            | Primitive::Line(p1, _)
+            | Primitive::Triangle(p1, _, _)
+            | Primitive::Quad(p1, _, _, _)
+            | Primitive::Tetra(p1, _, _, _) => p1.color(),
+        }
     }
 
     pub fn get_vertexinfo(&self) -> VertexInfo {
         match *self {
             Primitive::Point(p1) => Primitive::vertexinfo_point(p1),
             Primitive::Line(p1, p2) => Primitive::vertexinfo_line(p1, p2),
-            Primitive::Triangle(p1, p2, p3) => Primitive::vertexinfo_triangle(p1, p2, p3),
-            Primitive::Quad(p1, p2, p3, p4) => Primitive::vertexinfo_quad(p1, p2, p3, p4),
-            Primitive::Tetra(p1, p2, p3, p4) => Primitive::vertexinfo_tetra(p1, p2, p3, p4),
+            _ => {
+                let mut vertices = Vec::new();
+                let mut indices = Vec::new();
+                self.push_triangles(&mut vertices, &mut indices);
+                VertexInfo::new(vertices, indices)
+            }
         }
     }
 
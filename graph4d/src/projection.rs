@@ -0,0 +1,142 @@
+use std::f32::consts::PI;
+
+/// Limits for zooming a perspective projection.
+const MIN_FOV: f32 = PI / 18.0;
+const MAX_FOV: f32 = 2.0 * PI / 3.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProjectionMode {
+    Perspective,
+    /// Parallel projection showing `height` units of the scene vertically.
+    Orthographic {
+        height: f32,
+    },
+}
+
+/// How the camera-local 3D slice is projected onto the screen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Projection {
+    /// Vertical field of view in radians, used in the perspective mode.
+    pub fov: f32,
+    pub znear: f32,
+    pub zfar: f32,
+    pub mode: ProjectionMode,
+}
+
+impl Default for Projection {
+    fn default() -> Projection {
+        Projection {
+            fov: PI / 3.0,
+            znear: 0.1,
+            zfar: 1024.0,
+            mode: ProjectionMode::Perspective,
+        }
+    }
+}
+
+impl Projection {
+    /// Zooms in by `factor` (or out, if it's less than 1) - narrows the field of view, or
+    /// shrinks the visible area of an orthographic projection.
+    pub fn zoom(&mut self, factor: f32) {
+        match self.mode {
+            ProjectionMode::Perspective => {
+                let fov = 2.0 * ((self.fov / 2.0).tan() / factor).atan();
+                self.fov = fov.max(MIN_FOV).min(MAX_FOV);
+            }
+            ProjectionMode::Orthographic { ref mut height } => *height /= factor,
+        }
+    }
+
+    /// The projection matrix for a surface of the given size, in the column-major layout
+    /// expected by the shaders.
+    pub fn matrix(&self, width: u32, height: u32) -> [[f32; 4]; 4] {
+        let aspect_ratio = height as f32 / width as f32;
+        let (znear, zfar) = (self.znear, self.zfar);
+
+        match self.mode {
+            ProjectionMode::Perspective => {
+                let f = 1.0 / (self.fov / 2.0).tan();
+                [
+                    [f * aspect_ratio, 0.0, 0.0, 0.0],
+                    [0.0, f, 0.0, 0.0],
+                    [0.0, 0.0, (zfar + znear) / (zfar - znear), 1.0],
+                    [0.0, 0.0, -(2.0 * zfar * znear) / (zfar - znear), 0.0],
+                ]
+            }
+            ProjectionMode::Orthographic { height } => {
+                let f = 2.0 / height;
+                [
+                    [f * aspect_ratio, 0.0, 0.0, 0.0],
+                    [0.0, f, 0.0, 0.0],
+                    [0.0, 0.0, 2.0 / (zfar - znear), 0.0],
+                    [0.0, 0.0, -(zfar + znear) / (zfar - znear), 1.0],
+                ]
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Projection, ProjectionMode};
+    use std::f32::consts::PI;
+
+    fn project(matrix: [[f32; 4]; 4], p: [f32; 3]) -> [f32; 3] {
+        let mut clip = [0.0; 4];
+        for (i, c) in clip.iter_mut().enumerate() {
+            *c = matrix[0][i] * p[0] + matrix[1][i] * p[1] + matrix[2][i] * p[2] + matrix[3][i];
+        }
+        [clip[0] / clip[3], clip[1] / clip[3], clip[2] / clip[3]]
+    }
+
+    fn close(a: [f32; 3], b: [f32; 3]) -> bool {
+        a.iter().zip(b.iter()).all(|(a, b)| (a - b).abs() < 1e-4)
+    }
+
+    #[test]
+    fn test_perspective() {
+        let projection = Projection::default();
+        let matrix = projection.matrix(100, 100);
+        // the edge of the field of view lands on the edge of the screen
+        let y = (PI / 6.0).tan() * 10.0;
+        assert!(close(
+            project(matrix, [0.0, y, 10.0]),
+            [0.0, 1.0, project(matrix, [0.0, 0.0, 10.0])[2]]
+        ));
+        assert!(close(project(matrix, [0.0, 0.0, 0.1]), [0.0, 0.0, -1.0]));
+        assert!(close(project(matrix, [0.0, 0.0, 1024.0]), [0.0, 0.0, 1.0]));
+    }
+
+    #[test]
+    fn test_orthographic() {
+        let projection = Projection {
+            mode: ProjectionMode::Orthographic { height: 8.0 },
+            ..Default::default()
+        };
+        let matrix = projection.matrix(200, 100);
+        // the distance doesn't change the position on the screen
+        assert!(close(project(matrix, [8.0, 4.0, 0.1]), [1.0, 1.0, -1.0]));
+        assert!(close(project(matrix, [8.0, 4.0, 1024.0]), [1.0, 1.0, 1.0]));
+    }
+
+    #[test]
+    fn test_zoom() {
+        let mut projection = Projection::default();
+        projection.zoom(2.0);
+        assert!(((projection.fov / 2.0).tan() - (PI / 6.0).tan() / 2.0).abs() < 1e-5);
+        projection.zoom(0.5);
+        assert!((projection.fov - PI / 3.0).abs() < 1e-5);
+        projection.zoom(1000.0);
+        assert!((projection.fov - PI / 18.0).abs() < 1e-5);
+
+        let mut projection = Projection {
+            mode: ProjectionMode::Orthographic { height: 8.0 },
+            ..Default::default()
+        };
+        projection.zoom(2.0);
+        assert_eq!(
+            projection.mode,
+            ProjectionMode::Orthographic { height: 4.0 }
+        );
+    }
+}
@@ -0,0 +1,423 @@
+use super::shader::GliumVertex;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// A vertex after the projection, ready to be rasterized.
+#[derive(Clone, Copy)]
+struct ClipVertex {
+    clip: [f32; 4],
+    normal: [f32; 3],
+    color: [f32; 4],
+}
+
+impl ClipVertex {
+    fn new(vertex: &GliumVertex, matrix: &[[f32; 4]; 4]) -> ClipVertex {
+        let p = vertex.position();
+        let mut clip = [0.0; 4];
+        // the matrix is column-major, the same as the one passed to the shader
+        for (row, c) in clip.iter_mut().enumerate() {
+            *c = matrix[0][row] * p[0]
+                + matrix[1][row] * p[1]
+                + matrix[2][row] * p[2]
+                + matrix[3][row];
+        }
+        ClipVertex {
+            clip: clip,
+            normal: vertex.normal(),
+            color: vertex.color(),
+        }
+    }
+
+    fn lerp(&self, other: &ClipVertex, t: f32) -> ClipVertex {
+        let mut result = *self;
+        for i in 0..4 {
+            result.clip[i] += (other.clip[i] - self.clip[i]) * t;
+            result.color[i] += (other.color[i] - self.color[i]) * t;
+        }
+        for i in 0..3 {
+            result.normal[i] += (other.normal[i] - self.normal[i]) * t;
+        }
+        result
+    }
+
+    /// Distance from the near clipping plane; negative behind it.
+    fn near_distance(&self) -> f32 {
+        self.clip[2] + self.clip[3]
+    }
+}
+
+/// An RGBA image with a depth buffer that triangles can be drawn into without a GPU.
+///
+/// The rasterizer follows what the GL pipeline does with `VERTEX_SHADER` and `FRAGMENT_SHADER`:
+/// the same projection, the same lighting, a less-than depth test and standard alpha blending.
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    color: Vec<[f32; 4]>,
+    depth: Vec<f32>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Framebuffer {
+        Framebuffer {
+            width: width,
+            height: height,
+            color: vec![[0.0, 0.0, 0.0, 1.0]; width * height],
+            depth: vec![1.0; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn clear_color_and_depth(&mut self, color: (f32, f32, f32, f32), depth: f32) {
+        for pixel in self.color.iter_mut() {
+            *pixel = [color.0, color.1, color.2, color.3];
+        }
+        for d in self.depth.iter_mut() {
+            *d = depth;
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [f32; 4] {
+        self.color[y * self.width + x]
+    }
+
+    pub fn depth(&self, x: usize, y: usize) -> f32 {
+        self.depth[y * self.width + x]
+    }
+
+    /// Draws a triangle list. `matrix` and `light` are the uniforms the GL shaders get;
+    /// `depth_write` and `blend` correspond to the draw parameters.
+    pub fn draw(
+        &mut self,
+        vertices: &[GliumVertex],
+        indices: &[u32],
+        matrix: [[f32; 4]; 4],
+        light: [f32; 3],
+        depth_write: bool,
+        blend: bool,
+    ) {
+        for triangle in indices.chunks(3) {
+            if triangle.len() < 3 {
+                break;
+            }
+            let corners = [
+                ClipVertex::new(&vertices[triangle[0] as usize], &matrix),
+                ClipVertex::new(&vertices[triangle[1] as usize], &matrix),
+                ClipVertex::new(&vertices[triangle[2] as usize], &matrix),
+            ];
+            let polygon = Framebuffer::clip_near(&corners);
+            for i in 1..polygon.len().saturating_sub(1) {
+                self.fill_triangle(
+                    [polygon[0], polygon[i], polygon[i + 1]],
+                    light,
+                    depth_write,
+                    blend,
+                );
+            }
+        }
+    }
+
+    /// Cuts away the part of the triangle in front of the near plane.
+    fn clip_near(corners: &[ClipVertex; 3]) -> Vec<ClipVertex> {
+        let mut result = Vec::with_capacity(4);
+        for i in 0..3 {
+            let current = corners[i];
+            let next = corners[(i + 1) % 3];
+            let d1 = current.near_distance();
+            let d2 = next.near_distance();
+            if d1 >= 0.0 {
+                result.push(current);
+            }
+            if (d1 >= 0.0) != (d2 >= 0.0) {
+                result.push(current.lerp(&next, d1 / (d1 - d2)));
+            }
+        }
+        result
+    }
+
+    fn fill_triangle(
+        &mut self,
+        v: [ClipVertex; 3],
+        light: [f32; 3],
+        depth_write: bool,
+        blend: bool,
+    ) {
+        // window coordinates: x to the right, y down, depth in [0, 1]
+        let mut screen = [[0.0f32; 3]; 3];
+        for i in 0..3 {
+            let w = v[i].clip[3];
+            screen[i] = [
+                (v[i].clip[0] / w + 1.0) / 2.0 * self.width as f32,
+                (1.0 - v[i].clip[1] / w) / 2.0 * self.height as f32,
+                (v[i].clip[2] / w + 1.0) / 2.0,
+            ];
+        }
+
+        let edge = |a: [f32; 3], b: [f32; 3], x: f32, y: f32| {
+            (b[0] - a[0]) * (y - a[1]) - (b[1] - a[1]) * (x - a[0])
+        };
+        let area = edge(screen[0], screen[1], screen[2][0], screen[2][1]);
+        if area == 0.0 {
+            return;
+        }
+
+        let min_x = screen.iter().map(|s| s[0]).fold(f32::INFINITY, f32::min);
+        let max_x = screen
+            .iter()
+            .map(|s| s[0])
+            .fold(f32::NEG_INFINITY, f32::max);
+        let min_y = screen.iter().map(|s| s[1]).fold(f32::INFINITY, f32::min);
+        let max_y = screen
+            .iter()
+            .map(|s| s[1])
+            .fold(f32::NEG_INFINITY, f32::max);
+        let x0 = min_x.floor().max(0.0) as usize;
+        let x1 = (max_x.ceil().max(0.0) as usize).min(self.width);
+        let y0 = min_y.floor().max(0.0) as usize;
+        let y1 = (max_y.ceil().max(0.0) as usize).min(self.height);
+
+        let light = normalized(light);
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+                let b = [
+                    edge(screen[1], screen[2], px, py) / area,
+                    edge(screen[2], screen[0], px, py) / area,
+                    edge(screen[0], screen[1], px, py) / area,
+                ];
+                if b[0] < 0.0 || b[1] < 0.0 || b[2] < 0.0 {
+                    continue;
+                }
+
+                let depth = b[0] * screen[0][2] + b[1] * screen[1][2] + b[2] * screen[2][2];
+                let index = y * self.width + x;
+                if depth < 0.0 || depth > 1.0 || depth >= self.depth[index] {
+                    continue;
+                }
+
+                // perspective-correct weights for the vertex attributes
+                let mut weights = [
+                    b[0] / v[0].clip[3],
+                    b[1] / v[1].clip[3],
+                    b[2] / v[2].clip[3],
+                ];
+                let sum = weights[0] + weights[1] + weights[2];
+                for weight in weights.iter_mut() {
+                    *weight /= sum;
+                }
+                let mut normal = [0.0; 3];
+                let mut color = [0.0; 4];
+                for i in 0..3 {
+                    for j in 0..3 {
+                        normal[j] += v[i].normal[j] * weights[i];
+                    }
+                    for j in 0..4 {
+                        color[j] += v[i].color[j] * weights[i];
+                    }
+                }
+
+                let src = shade(normal, color, light);
+                let dst = self.color[index];
+                self.color[index] = if blend {
+                    let a = src[3];
+                    [
+                        src[0] * a + dst[0] * (1.0 - a),
+                        src[1] * a + dst[1] * (1.0 - a),
+                        src[2] * a + dst[2] * (1.0 - a),
+                        src[3] * a + dst[3] * (1.0 - a),
+                    ]
+                } else {
+                    src
+                };
+                if depth_write {
+                    self.depth[index] = depth;
+                }
+            }
+        }
+    }
+
+    /// Writes the image as a binary PPM. Colors are clamped to [0, 1]; alpha is dropped.
+    pub fn write_ppm<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        write!(writer, "P6\n{} {}\n255\n", self.width, self.height)?;
+        let mut bytes = Vec::with_capacity(self.width * self.height * 3);
+        for pixel in self.color.iter() {
+            for channel in pixel.iter().take(3) {
+                bytes.push((channel.max(0.0).min(1.0) * 255.0).round() as u8);
+            }
+        }
+        writer.write_all(&bytes)
+    }
+
+    pub fn save_ppm<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_ppm(BufWriter::new(File::create(path)?))
+    }
+}
+
+fn normalized(v: [f32; 3]) -> [f32; 3] {
+    let len = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    if len == 0.0 {
+        v
+    } else {
+        [v[0] / len, v[1] / len, v[2] / len]
+    }
+}
+
+/// The same computation as in `FRAGMENT_SHADER`.
+fn shade(normal: [f32; 3], color: [f32; 4], light: [f32; 3]) -> [f32; 4] {
+    let normal = normalized(normal);
+    let brightness = (normal[0] * light[0] + normal[1] * light[1] + normal[2] * light[2]).abs();
+    let dark = normalized([color[0], color[1], color[2]]);
+    let dark = [dark[0] * 0.1, dark[1] * 0.1, dark[2] * 0.1, color[3]];
+    let mut result = [0.0; 4];
+    for i in 0..4 {
+        result[i] = dark[i] + (color[i] - dark[i]) * brightness;
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::geometry::Vector;
+    use super::super::primitive::Color;
+    use super::super::shader::GliumVertex;
+    use super::Framebuffer;
+    use std::f32::consts::PI;
+
+    // a plain perspective projection, as used by the renderer for a square viewport
+    fn matrix() -> [[f32; 4]; 4] {
+        let (znear, zfar) = (0.1f32, 1024.0f32);
+        let f = 1.0 / (PI / 6.0).tan();
+        [
+            [f, 0.0, 0.0, 0.0],
+            [0.0, f, 0.0, 0.0],
+            [0.0, 0.0, (zfar + znear) / (zfar - znear), 1.0],
+            [0.0, 0.0, -(2.0 * zfar * znear) / (zfar - znear), 0.0],
+        ]
+    }
+
+    fn triangle(z: f64, size: f64, color: Color) -> Vec<GliumVertex> {
+        let normal = Vector::new(0.0, 0.0, -1.0, 0.0);
+        vec![
+            GliumVertex::new(Vector::new(-size, -size, z, 0.0), normal, color),
+            GliumVertex::new(Vector::new(size, -size, z, 0.0), normal, color),
+            GliumVertex::new(Vector::new(0.0, size, z, 0.0), normal, color),
+        ]
+    }
+
+    const LIGHT: [f32; 3] = [0.0, 0.0, -1.0];
+
+    #[test]
+    fn test_triangle_coverage() {
+        let mut fb = Framebuffer::new(32, 32);
+        let red = Color::rgb(1.0, 0.0, 0.0);
+        fb.draw(
+            &triangle(2.0, 0.5, red),
+            &[0, 1, 2],
+            matrix(),
+            LIGHT,
+            true,
+            false,
+        );
+        let center = fb.pixel(16, 16);
+        assert!((center[0] - 1.0).abs() < 1e-5);
+        assert_eq!(center[1], 0.0);
+        assert_eq!(fb.pixel(0, 0), [0.0, 0.0, 0.0, 1.0]);
+        assert!(fb.depth(16, 16) < 1.0);
+    }
+
+    #[test]
+    fn test_depth_test() {
+        let red = Color::rgb(1.0, 0.0, 0.0);
+        let green = Color::rgb(0.0, 1.0, 0.0);
+        let mut fb = Framebuffer::new(32, 32);
+        fb.draw(
+            &triangle(2.0, 0.5, red),
+            &[0, 1, 2],
+            matrix(),
+            LIGHT,
+            true,
+            false,
+        );
+        fb.draw(
+            &triangle(3.0, 0.5, green),
+            &[0, 1, 2],
+            matrix(),
+            LIGHT,
+            true,
+            false,
+        );
+        assert!(fb.pixel(16, 16)[0] > 0.99);
+
+        let mut fb = Framebuffer::new(32, 32);
+        fb.draw(
+            &triangle(3.0, 0.5, green),
+            &[0, 1, 2],
+            matrix(),
+            LIGHT,
+            true,
+            false,
+        );
+        fb.draw(
+            &triangle(2.0, 0.5, red),
+            &[0, 1, 2],
+            matrix(),
+            LIGHT,
+            true,
+            false,
+        );
+        assert!(fb.pixel(16, 16)[0] > 0.99);
+    }
+
+    #[test]
+    fn test_alpha_blending() {
+        let mut fb = Framebuffer::new(32, 32);
+        let translucent = Color::rgba(0.0, 0.0, 1.0, 0.25);
+        fb.clear_color_and_depth((1.0, 0.0, 0.0, 1.0), 1.0);
+        fb.draw(
+            &triangle(2.0, 0.5, translucent),
+            &[0, 1, 2],
+            matrix(),
+            LIGHT,
+            false,
+            true,
+        );
+        let center = fb.pixel(16, 16);
+        assert!((center[0] - 0.75).abs() < 1e-5);
+        assert!((center[2] - 0.25).abs() < 1e-5);
+        assert_eq!(fb.depth(16, 16), 1.0);
+    }
+
+    #[test]
+    fn test_near_clipping() {
+        // a triangle reaching behind the camera still draws its visible part
+        let mut fb = Framebuffer::new(32, 32);
+        let normal = Vector::new(0.0, 1.0, 0.0, 0.0);
+        let white = Color::rgb(1.0, 1.0, 1.0);
+        let vertices = vec![
+            GliumVertex::new(Vector::new(-1.0, -0.5, -1.0, 0.0), normal, white),
+            GliumVertex::new(Vector::new(1.0, -0.5, -1.0, 0.0), normal, white),
+            GliumVertex::new(Vector::new(0.0, -0.5, 5.0, 0.0), normal, white),
+        ];
+        fb.draw(&vertices, &[0, 1, 2], matrix(), LIGHT, true, false);
+        assert!(fb.pixel(16, 31)[0] > 0.0);
+    }
+
+    #[test]
+    fn test_ppm() {
+        let mut fb = Framebuffer::new(2, 1);
+        fb.clear_color_and_depth((1.0, 0.5, 0.0, 1.0), 1.0);
+        let mut out = Vec::new();
+        fb.write_ppm(&mut out).unwrap();
+        assert_eq!(&out[..11], b"P6\n2 1\n255\n");
+        assert_eq!(&out[11..], &[255, 128, 0, 255, 128, 0]);
+    }
+}
@@ -1,28 +1,84 @@
 use super::camera::Camera;
-use super::geometry::{Matrix, Vector};
+use super::geometry::{Aabb, Matrix, Vector};
 use super::primitive::{Color, Primitive, Vertex};
-use super::shader::{FRAGMENT_SHADER, VERTEX_SHADER};
+use super::projection::Projection;
+use super::raster::Framebuffer;
+use super::shader::{GliumVertex, FRAGMENT_SHADER, VERTEX_SHADER};
+use super::slice::{Batch, Slice};
 use glium;
 use glium::backend::Facade;
 use glium::index::PrimitiveType;
+use glium::uniforms::Uniforms;
 use glium::{Blend, IndexBuffer, Program, Surface, VertexBuffer};
+use std::ops::Range;
+
+/// What happened to the queued geometry during the last slicing.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RenderStats {
+    /// Objects queued with bounds.
+    pub objects: usize,
+    /// Objects skipped because their bounds were away from the hyperplane.
+    pub objects_culled: usize,
+    /// All queued primitives.
+    pub primitives: usize,
+    /// Primitives skipped together with their objects.
+    pub primitives_culled: usize,
+    /// Primitives intersected with the hyperplane.
+    pub primitives_sliced: usize,
+    /// Primitives that the hyperplane actually passed through.
+    pub primitives_visible: usize,
+}
+
+/// A group of queued primitives that lies within `bounds`.
+struct QueuedObject {
+    bounds: Aabb,
+    primitives: Range<usize>,
+}
 
 pub struct Renderer {
     current_transform: Matrix,
     matrix_stack: Vec<Matrix>,
     prim_queue: Vec<Primitive>,
+    objects: Vec<QueuedObject>,
+    open_object: Option<QueuedObject>,
+    stats: RenderStats,
     current_color: Color,
-    shader: Program,
+    shader: Option<Program>,
+    projection: Projection,
+    // the batches of the last frame, refilled every frame to reuse their memory
+    opaque: Batch,
+    translucent: Batch,
+    opaque_buffers: BatchBuffers,
+    translucent_buffers: BatchBuffers,
 }
 
+const LIGHT: [f32; 3] = [0.0, -0.6, -0.8];
+
 impl Renderer {
     pub fn new<F: Facade>(display: &F) -> Renderer {
+        let mut renderer = Renderer::headless();
+        renderer.shader =
+            Some(Program::from_source(display, VERTEX_SHADER, FRAGMENT_SHADER, None).unwrap());
+        renderer
+    }
+
+    /// Creates a renderer without a GL context. It can only draw with `render_software`,
+    /// until it is first used with `render`.
+    pub fn headless() -> Renderer {
         Renderer {
             current_transform: Matrix::identity(),
             matrix_stack: Vec::new(),
             prim_queue: Vec::new(),
+            objects: Vec::new(),
+            open_object: None,
+            stats: RenderStats::default(),
             current_color: Color::rgb(1.0, 1.0, 1.0),
-            shader: Program::from_source(display, VERTEX_SHADER, FRAGMENT_SHADER, None).unwrap(),
+            shader: None,
+            projection: Projection::default(),
+            opaque: Batch::new(),
+            translucent: Batch::new(),
+            opaque_buffers: BatchBuffers::new(),
+            translucent_buffers: BatchBuffers::new(),
         }
     }
 
@@ -68,6 +124,42 @@ impl Renderer {
         self.current_color = color;
     }
 
+    /// Starts an object - a group of primitives lying within `bounds`, given in the current
+    /// coordinates. If the camera hyperplane misses the bounds, the whole object is skipped
+    /// without slicing its primitives. Objects can't be nested; `cube` and `tesseract` start
+    /// their own objects when none is open.
+    pub fn begin_object(&mut self, bounds: Aabb) {
+        self.end_object();
+        self.open_object = Some(QueuedObject {
+            bounds: bounds.transformed(self.current_transform),
+            primitives: self.prim_queue.len()..self.prim_queue.len(),
+        });
+    }
+
+    pub fn end_object(&mut self) {
+        if let Some(mut object) = self.open_object.take() {
+            object.primitives.end = self.prim_queue.len();
+            self.objects.push(object);
+        }
+    }
+
+    /// Queues the primitives added by `f` as an object within `bounds`, unless an object is
+    /// already open.
+    fn with_bounds<F: FnOnce(&mut Renderer)>(&mut self, bounds: Aabb, f: F) {
+        if self.open_object.is_some() {
+            f(self);
+        } else {
+            self.begin_object(bounds);
+            f(self);
+            self.end_object();
+        }
+    }
+
+    /// Counters from the last frame.
+    pub fn stats(&self) -> RenderStats {
+        self.stats
+    }
+
     pub fn tetrahedron(&mut self, v1: Vector, v2: Vector, v3: Vector, v4: Vector) {
         self.prim_queue.push(Primitive::Tetra(
             Vertex::new(self.current_transform * v1, self.current_color),
@@ -78,6 +170,15 @@ impl Renderer {
     }
 
     pub fn cube(&mut self, a: f64) {
+        let half = a / 2.0;
+        let bounds = Aabb::new(
+            Vector::new(-half, -half, -half, 0.0),
+            Vector::new(half, half, half, 0.0),
+        );
+        self.with_bounds(bounds, |renderer| renderer.cube_primitives(a));
+    }
+
+    fn cube_primitives(&mut self, a: f64) {
         let v = [
             Vector::new(-a / 2.0, -a / 2.0, -a / 2.0, 0.0),
             Vector::new(-a / 2.0, -a / 2.0, a / 2.0, 0.0),
@@ -109,6 +210,15 @@ impl Renderer {
     }
 
     pub fn tesseract(&mut self, a: f64) {
+        let half = a / 2.0;
+        let bounds = Aabb::new(
+            Vector::new(-half, -half, -half, -half),
+            Vector::new(half, half, half, half),
+        );
+        self.with_bounds(bounds, |renderer| renderer.tesseract_primitives(a));
+    }
+
+    fn tesseract_primitives(&mut self, a: f64) {
         let v = [
             Vector::new(-a / 2.0, -a / 2.0, -a / 2.0, -a / 2.0),
             Vector::new(-a / 2.0, -a / 2.0, -a / 2.0, a / 2.0),
@@ -138,22 +248,55 @@ impl Renderer {
         self.cube_vertex_array(&v, [1, 3, 5, 7, 9, 11, 13, 15]);
     }
 
-    fn get_perspective_matrix<S: Surface>(&self, surface: &S) -> [[f32; 4]; 4] {
-        let (width, height) = surface.get_dimensions();
-        let aspect_ratio = height as f32 / width as f32;
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
 
-        let fov: f32 = 3.141592 / 3.0;
-        let zfar = 1024.0;
-        let znear = 0.1;
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+    }
+
+    /// Intersects the queued primitives with the camera hyperplane and converts them to the
+    /// camera's local coordinates. Empties the queue.
+    pub fn slice<C: Camera>(&mut self, camera: &C) -> Slice {
+        let mut local_queue = Vec::new();
+        self.matrix_stack.clear();
+        self.end_object();
 
-        let f = 1.0 / (fov / 2.0).tan();
+        let hyperplane = camera.get_hyperplane();
+        let mut stats = RenderStats {
+            objects: self.objects.len(),
+            primitives: self.prim_queue.len(),
+            ..Default::default()
+        };
+        let mut culled = vec![false; self.prim_queue.len()];
+        for object in self.objects.iter() {
+            if !object.bounds.crosses(hyperplane) {
+                stats.objects_culled += 1;
+                stats.primitives_culled += object.primitives.len();
+                for c in culled[object.primitives.clone()].iter_mut() {
+                    *c = true;
+                }
+            }
+        }
 
-        [
-            [f * aspect_ratio, 0.0, 0.0, 0.0],
-            [0.0, f, 0.0, 0.0],
-            [0.0, 0.0, (zfar + znear) / (zfar - znear), 1.0],
-            [0.0, 0.0, -(2.0 * zfar * znear) / (zfar - znear), 0.0],
-        ]
+        for (prim, &culled) in self.prim_queue.iter().zip(culled.iter()) {
+            if culled {
+                continue;
+            }
+            stats.primitives_sliced += 1;
+            if let Some(prim) = prim.intersect(hyperplane) {
+                stats.primitives_visible += 1;
+                local_queue
+                    .push(prim.map(|v| Vertex::new(camera.calculate_local(v.point()), v.color())));
+            }
+        }
+        self.prim_queue.clear();
+        self.objects.clear();
+        self.current_transform = Matrix::identity();
+        self.stats = stats;
+
+        Slice::new(local_queue)
     }
 
     pub fn render<F: Facade, C: Camera, S: Surface>(
@@ -162,56 +305,234 @@ impl Renderer {
         camera: &C,
         surface: &mut S,
     ) {
-        let mut local_queue = Vec::new();
-        self.matrix_stack.clear();
+        let slice = self.slice(camera);
+        self.submit(facade, &slice, surface);
+    }
 
-        for prim in self.prim_queue.iter() {
-            if let Some(prim) = prim.intersect(camera.get_hyperplane()) {
-                local_queue
-                    .push(prim.map(|v| Vertex::new(camera.calculate_local(v.point()), v.color())));
-            }
+    /// Draws an already sliced frame with OpenGL.
+    pub fn submit<F: Facade, S: Surface>(&mut self, facade: &F, slice: &Slice, surface: &mut S) {
+        if self.shader.is_none() {
+            self.shader =
+                Some(Program::from_source(facade, VERTEX_SHADER, FRAGMENT_SHADER, None).unwrap());
         }
-        self.prim_queue.clear();
+        let shader = self.shader.as_ref().unwrap();
+
+        let (width, height) = surface.get_dimensions();
+        let matrix = self.projection.matrix(width, height);
 
-        let matrix = self.get_perspective_matrix(surface);
-
-        for prim in local_queue.iter() {
-            let vertexinfo = prim.get_vertexinfo();
-            let vertices = vertexinfo.vertices();
-            let indices = vertexinfo.indices();
-
-            let params = glium::DrawParameters {
-                depth: glium::Depth {
-                    test: glium::draw_parameters::DepthTest::IfLess,
-                    write: vertices[0].color()[3] > 0.99,
-                    ..Default::default()
-                },
-                blend: if vertices[0].color()[3] < 1.0 {
-                    Blend::alpha_blending()
-                } else {
-                    Default::default()
-                },
+        slice.fill_batches(&mut self.opaque, &mut self.translucent);
+        let uniforms = uniform! {
+            matrix: matrix,
+            u_light: LIGHT
+        };
+
+        let params = glium::DrawParameters {
+            depth: glium::Depth {
+                test: glium::draw_parameters::DepthTest::IfLess,
+                write: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        self.opaque_buffers
+            .draw(facade, &self.opaque, surface, shader, &uniforms, &params);
+
+        let params = glium::DrawParameters {
+            depth: glium::Depth {
+                test: glium::draw_parameters::DepthTest::IfLess,
+                write: false,
                 ..Default::default()
-            };
-
-            let vertices_buf = VertexBuffer::new(facade, &vertices).unwrap();
-            let indices_buf =
-                IndexBuffer::new(facade, PrimitiveType::TrianglesList, &indices).unwrap();
-
-            surface
-                .draw(
-                    &vertices_buf,
-                    &indices_buf,
-                    &self.shader,
-                    &uniform! {
-                        matrix: matrix,
-                        u_light: [0.0, -0.6, -0.8f32]
-                    },
-                    &params,
-                )
-                .unwrap();
+            },
+            blend: Blend::alpha_blending(),
+            ..Default::default()
+        };
+        self.translucent_buffers.draw(
+            facade,
+            &self.translucent,
+            surface,
+            shader,
+            &uniforms,
+            &params,
+        );
+    }
+
+    /// Renders the queued primitives into `framebuffer` on the CPU, with the same results as
+    /// `render` would give on the GPU.
+    pub fn render_software<C: Camera>(&mut self, camera: &C, framebuffer: &mut Framebuffer) {
+        let slice = self.slice(camera);
+        self.submit_software(&slice, framebuffer);
+    }
+
+    /// Draws an already sliced frame into `framebuffer` on the CPU.
+    pub fn submit_software(&mut self, slice: &Slice, framebuffer: &mut Framebuffer) {
+        let matrix = self
+            .projection
+            .matrix(framebuffer.width() as u32, framebuffer.height() as u32);
+
+        slice.fill_batches(&mut self.opaque, &mut self.translucent);
+        framebuffer.draw(
+            self.opaque.vertices(),
+            self.opaque.indices(),
+            matrix,
+            LIGHT,
+            true,
+            false,
+        );
+        framebuffer.draw(
+            self.translucent.vertices(),
+            self.translucent.indices(),
+            matrix,
+            LIGHT,
+            false,
+            true,
+        );
+    }
+}
+
+/// Vertex and index buffers kept between frames, so that a batch is uploaded without
+/// allocating anything unless it has outgrown the previous ones.
+struct BatchBuffers {
+    buffers: Option<(VertexBuffer<GliumVertex>, IndexBuffer<u32>)>,
+}
+
+impl BatchBuffers {
+    fn new() -> BatchBuffers {
+        BatchBuffers { buffers: None }
+    }
+
+    fn draw<F: Facade, S: Surface, U: Uniforms>(
+        &mut self,
+        facade: &F,
+        batch: &Batch,
+        surface: &mut S,
+        shader: &Program,
+        uniforms: &U,
+        params: &glium::DrawParameters,
+    ) {
+        if batch.is_empty() {
+            return;
         }
+        let vertex_count = batch.vertices().len();
+        let index_count = batch.indices().len();
 
-        self.current_transform = Matrix::identity();
+        let too_small = match self.buffers {
+            Some((ref vertices, ref indices)) => {
+                vertices.len() < vertex_count || indices.len() < index_count
+            }
+            None => true,
+        };
+        if too_small {
+            // grow to the next power of two, so that slowly growing frames don't reallocate
+            // every time
+            let vertices =
+                VertexBuffer::empty_dynamic(facade, vertex_count.next_power_of_two()).unwrap();
+            let indices = IndexBuffer::empty_dynamic(
+                facade,
+                PrimitiveType::TrianglesList,
+                index_count.next_power_of_two(),
+            )
+            .unwrap();
+            self.buffers = Some((vertices, indices));
+        }
+
+        let (ref vertices, ref indices) = *self.buffers.as_ref().unwrap();
+        let vertices = vertices.slice(0..vertex_count).unwrap();
+        let indices = indices.slice(0..index_count).unwrap();
+        vertices.write(batch.vertices());
+        indices.write(batch.indices());
+
+        surface
+            .draw(vertices, indices, shader, uniforms, params)
+            .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::camera::Camera;
+    use super::super::geometry::{Hyperplane, Matrix, Vector};
+    use super::super::primitive::Color;
+    use super::super::raster::Framebuffer;
+    use super::Renderer;
+
+    /// Looks at the w = 0 hyperplane from 3 units behind the origin.
+    struct TestCamera;
+
+    impl Camera for TestCamera {
+        fn get_hyperplane(&self) -> Hyperplane {
+            Hyperplane::new(Vector::new(0.0, 0.0, 0.0, 1.0), 0.0)
+        }
+
+        fn calculate_local(&self, vec: Vector) -> Vector {
+            vec - Vector::new(0.0, 0.0, -3.0, 0.0)
+        }
+    }
+
+    #[test]
+    fn test_render_software() {
+        let mut renderer = Renderer::headless();
+        renderer.set_color(Color::rgb(0.6, 0.6, 0.0));
+        renderer.tesseract(1.0);
+        let mut framebuffer = Framebuffer::new(64, 48);
+        renderer.render_software(&TestCamera, &mut framebuffer);
+
+        // the tesseract is sliced into a cube in the middle of the image
+        let center = framebuffer.pixel(32, 24);
+        assert!(center[0] > 0.0 && center[1] > 0.0);
+        assert_eq!(center[2], 0.0);
+        assert_eq!(framebuffer.pixel(0, 0), [0.0, 0.0, 0.0, 1.0]);
+
+        // the queue is consumed by rendering
+        let mut empty = Framebuffer::new(64, 48);
+        renderer.render_software(&TestCamera, &mut empty);
+        assert_eq!(empty.pixel(32, 24), [0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn test_slice() {
+        let mut renderer = Renderer::headless();
+        renderer.tesseract(1.0);
+        let slice = renderer.slice(&TestCamera);
+        assert!(!slice.is_empty());
+
+        // every vertex of the sliced cube is in its local bounds
+        for vertexinfo in slice.vertexinfos() {
+            for vertex in vertexinfo.vertices() {
+                let p = vertex.position();
+                assert!(p[0].abs() <= 0.5 + 1e-6 && p[1].abs() <= 0.5 + 1e-6);
+                assert!(p[2] >= 2.5 - 1e-6 && p[2] <= 3.5 + 1e-6);
+            }
+        }
+
+        // the hyperplane misses a tesseract moved along w
+        renderer.apply_matrix(Matrix::translation(Vector::new(0.0, 0.0, 0.0, 2.0)));
+        renderer.tesseract(1.0);
+        assert!(renderer.slice(&TestCamera).is_empty());
+    }
+
+    #[test]
+    fn test_culling() {
+        let mut renderer = Renderer::headless();
+        renderer.tesseract(1.0);
+        renderer.push_matrix();
+        renderer.apply_matrix(Matrix::translation(Vector::new(0.0, 0.0, 0.0, 2.0)));
+        renderer.tesseract(1.0);
+        renderer.cube(1.0);
+        renderer.pop_matrix();
+        let slice = renderer.slice(&TestCamera);
+
+        // a tesseract is 40 tetrahedra and a cube 5; only the first tesseract is sliced
+        let stats = renderer.stats();
+        assert_eq!(stats.objects, 3);
+        assert_eq!(stats.objects_culled, 2);
+        assert_eq!(stats.primitives, 85);
+        assert_eq!(stats.primitives_culled, 45);
+        assert_eq!(stats.primitives_sliced, 40);
+        assert_eq!(stats.primitives_visible, slice.len());
+
+        // culling doesn't change what is visible
+        let mut renderer = Renderer::headless();
+        renderer.tesseract(1.0);
+        assert_eq!(renderer.slice(&TestCamera).len(), slice.len());
     }
 }
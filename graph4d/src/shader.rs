@@ -17,6 +17,14 @@ impl GliumVertex {
         }
     }
 
+    pub fn position(&self) -> [f32; 3] {
+        self.position
+    }
+
+    pub fn normal(&self) -> [f32; 3] {
+        self.normal
+    }
+
     pub fn color(&self) -> [f32; 4] {
         self.color
     }
@@ -0,0 +1,236 @@
+use super::primitive::Primitive;
+use super::shader::{GliumVertex, VertexInfo};
+use std::cmp::Ordering;
+
+/// The geometry of a single frame - the parts of the queued primitives lying in the camera
+/// hyperplane, in the camera's local 3D coordinates.
+///
+/// Producing a `Slice` doesn't need a display, so it can be inspected, exported or drawn by
+/// any backend.
+pub struct Slice {
+    primitives: Vec<Primitive>,
+}
+
+impl Slice {
+    pub fn new(primitives: Vec<Primitive>) -> Slice {
+        Slice {
+            primitives: primitives,
+        }
+    }
+
+    pub fn primitives(&self) -> &[Primitive] {
+        &self.primitives
+    }
+
+    pub fn len(&self) -> usize {
+        self.primitives.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.primitives.is_empty()
+    }
+
+    /// The vertices and indices of every primitive, ready to be drawn.
+    pub fn vertexinfos(&self) -> Vec<VertexInfo> {
+        self.primitives.iter().map(|p| p.get_vertexinfo()).collect()
+    }
+
+    /// Merges the triangles of the frame into two batches - the opaque and the translucent
+    /// ones - so that each can be drawn at once. Points and lines are left out.
+    ///
+    /// The translucent triangles are sorted back to front, so they blend correctly when drawn
+    /// after the opaque ones.
+    pub fn batches(&self) -> (Batch, Batch) {
+        let mut opaque = Batch::new();
+        let mut translucent = Batch::new();
+        self.fill_batches(&mut opaque, &mut translucent);
+        (opaque, translucent)
+    }
+
+    /// Like `batches`, but refills batches kept from an earlier frame, so that their memory is
+    /// reused.
+    pub fn fill_batches(&self, opaque: &mut Batch, translucent: &mut Batch) {
+        opaque.clear();
+        translucent.clear();
+        for primitive in &self.primitives {
+            if primitive.color().a() < 1.0 {
+                translucent.push(primitive);
+            } else {
+                opaque.push(primitive);
+            }
+        }
+        translucent.sort_back_to_front();
+    }
+}
+
+/// Triangles of many primitives in a single vertex and index list.
+pub struct Batch {
+    vertices: Vec<GliumVertex>,
+    indices: Vec<u32>,
+    // the triangles with their depths, kept only to reuse the memory when sorting
+    sorted: Vec<(f32, [u32; 3])>,
+}
+
+impl Batch {
+    pub fn new() -> Batch {
+        Batch {
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            sorted: Vec::new(),
+        }
+    }
+
+    /// Appends the triangles of a primitive. Points and lines aren't triangles and are skipped.
+    pub fn push(&mut self, primitive: &Primitive) {
+        primitive.push_triangles(&mut self.vertices, &mut self.indices);
+    }
+
+    /// Empties the batch, keeping its memory for the next frame.
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+        self.indices.clear();
+    }
+
+    /// Orders the triangles from the furthest to the nearest, by the depth of their centroids
+    /// along the view direction (the z axis). Unlike the distance from the camera, this is also
+    /// right for an orthographic projection, where every ray goes along z.
+    pub fn sort_back_to_front(&mut self) {
+        let vertices = &self.vertices;
+        let depth = |triangle: &[u32]| {
+            triangle
+                .iter()
+                .map(|&i| vertices[i as usize].position()[2] / 3.0)
+                .sum::<f32>()
+        };
+
+        self.sorted.clear();
+        self.sorted.extend(
+            self.indices
+                .chunks(3)
+                .map(|t| (depth(t), [t[0], t[1], t[2]])),
+        );
+        self.sorted
+            .sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+        for (triangle, &(_, sorted)) in self.indices.chunks_mut(3).zip(self.sorted.iter()) {
+            triangle.copy_from_slice(&sorted);
+        }
+    }
+
+    pub fn vertices(&self) -> &[GliumVertex] {
+        &self.vertices
+    }
+
+    pub fn indices(&self) -> &[u32] {
+        &self.indices
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+}
+
+impl Default for Batch {
+    fn default() -> Batch {
+        Batch::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::geometry::Vector;
+    use super::super::primitive::{Color, Primitive, Vertex};
+    use super::Slice;
+
+    fn triangle_at(z: f64, color: Color) -> Primitive {
+        Primitive::Triangle(
+            Vertex::new(Vector::new(0.0, 0.0, z, 0.0), color),
+            Vertex::new(Vector::new(1.0, 0.0, z, 0.0), color),
+            Vertex::new(Vector::new(0.0, 1.0, z, 0.0), color),
+        )
+    }
+
+    fn triangle(color: Color) -> Primitive {
+        triangle_at(1.0, color)
+    }
+
+    #[test]
+    fn test_batches() {
+        let opaque = Color::rgb(1.0, 0.0, 0.0);
+        let translucent = Color::rgba(0.0, 0.0, 1.0, 0.5);
+        let point = Primitive::Point(Vertex::new(Vector::new(0.0, 0.0, 1.0, 0.0), opaque));
+        let slice = Slice::new(vec![
+            triangle(opaque),
+            triangle(translucent),
+            point,
+            triangle(opaque),
+        ]);
+
+        let (opaque, translucent) = slice.batches();
+        assert_eq!(opaque.vertices().len(), 6);
+        assert_eq!(opaque.indices(), &[0, 1, 2, 3, 4, 5]);
+        assert_eq!(translucent.vertices().len(), 3);
+        assert_eq!(translucent.indices(), &[0, 1, 2]);
+        assert!(translucent.vertices().iter().all(|v| v.color()[3] == 0.5));
+    }
+
+    #[test]
+    fn test_refill_batches() {
+        let opaque = Color::rgb(1.0, 0.0, 0.0);
+        let translucent = Color::rgba(0.0, 0.0, 1.0, 0.5);
+        let first = Slice::new(vec![
+            triangle(opaque),
+            triangle(translucent),
+            triangle(opaque),
+        ]);
+        let second = Slice::new(vec![triangle(translucent)]);
+
+        let (mut opaque, mut translucent) = first.batches();
+        second.fill_batches(&mut opaque, &mut translucent);
+        assert!(opaque.is_empty());
+        assert!(opaque.vertices().is_empty());
+        assert_eq!(translucent.vertices().len(), 3);
+        assert_eq!(translucent.indices(), &[0, 1, 2]);
+    }
+
+    #[test]
+    fn test_translucent_back_to_front() {
+        let near = Color::rgba(1.0, 0.0, 0.0, 0.5);
+        let far = Color::rgba(0.0, 0.0, 1.0, 0.5);
+        let middle = Color::rgba(0.0, 1.0, 0.0, 0.5);
+        let tetra = Primitive::Tetra(
+            Vertex::new(Vector::new(0.0, 0.0, 3.0, 0.0), middle),
+            Vertex::new(Vector::new(1.0, 0.0, 3.0, 0.0), middle),
+            Vertex::new(Vector::new(0.0, 1.0, 3.0, 0.0), middle),
+            Vertex::new(Vector::new(0.0, 0.0, 4.0, 0.0), middle),
+        );
+        let slice = Slice::new(vec![triangle_at(1.0, near), tetra, triangle_at(8.0, far)]);
+
+        let (_, translucent) = slice.batches();
+        let indices = translucent.indices();
+        assert_eq!(indices.len(), 18);
+        let colors: Vec<[f32; 4]> = indices
+            .chunks(3)
+            .map(|triangle| translucent.vertices()[triangle[0] as usize].color())
+            .collect();
+        assert_eq!(colors.first(), Some(&[0.0, 0.0, 1.0, 0.5]));
+        assert_eq!(colors.last(), Some(&[1.0, 0.0, 0.0, 0.5]));
+        assert!(colors[1..5].iter().all(|c| *c == [0.0, 1.0, 0.0, 0.5]));
+    }
+
+    #[test]
+    fn test_sort_by_depth() {
+        // the triangle off to the side is further from the camera, but nearer along z
+        let side = Color::rgba(1.0, 0.0, 0.0, 0.5);
+        let ahead = Color::rgba(0.0, 0.0, 1.0, 0.5);
+        let side_triangle = Primitive::Triangle(
+            Vertex::new(Vector::new(20.0, 0.0, 2.0, 0.0), side),
+            Vertex::new(Vector::new(21.0, 0.0, 2.0, 0.0), side),
+            Vertex::new(Vector::new(20.0, 1.0, 2.0, 0.0), side),
+        );
+        let slice = Slice::new(vec![side_triangle, triangle_at(5.0, ahead)]);
+
+        let (_, translucent) = slice.batches();
+        let first = translucent.indices()[0] as usize;
+        assert_eq!(translucent.vertices()[first].color(), [0.0, 0.0, 1.0, 0.5]);
+    }
+}